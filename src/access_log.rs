@@ -0,0 +1,411 @@
+use crate::config::Configuration;
+use crate::url_path::{encode_path, normalize_path};
+use anyhow::{Context, Result, ensure};
+use clap::ValueEnum;
+use hyper::Request;
+use hyper::header::{REFERER, USER_AGENT};
+use jiff::Timestamp;
+use jiff::tz::TimeZone;
+use serde::Serialize;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Maximum number of log lines waiting to be written
+const MAX_QUEUED_LINES: usize = 10_000;
+
+/// Replaces update secrets in logged request targets
+const SECRET_PLACEHOLDER: &str = "***";
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum AccessLogFormat {
+    Off,
+    Common,
+    Combined,
+    Json,
+}
+
+/// IP address range in CIDR notation like "10.0.0.0/8" or a single IP address
+#[derive(Clone)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for IpRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (addr, prefix_len) = value.split_once("/").unwrap_or((value, ""));
+        let addr: IpAddr = addr
+            .parse()
+            .context(format!("Invalid IP address in '{value}'"))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = if prefix_len.is_empty() {
+            max_len
+        } else {
+            prefix_len
+                .parse()
+                .context(format!("Invalid prefix length in '{value}'"))?
+        };
+        ensure!(
+            prefix_len <= max_len,
+            "Prefix length in '{value}' is too big"
+        );
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Writes one line per served HTTP request
+pub struct AccessLog {
+    format: AccessLogFormat,
+    sample_rate: f64,
+    excluded_paths: Vec<String>,
+    trusted_proxies: Vec<IpRange>,
+    // Lines are written by a separate thread to keep file access off the request path
+    output: Option<SyncSender<String>>,
+}
+
+/// Request details captured before the request is handled
+pub struct AccessEntry {
+    time: Timestamp,
+    client: IpAddr,
+    method: String,
+    target: String,
+    version: String,
+    user_agent: Option<String>,
+    referer: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    time: String,
+    client_ip: String,
+    method: &'a str,
+    path: &'a str,
+    protocol: &'a str,
+    status: u16,
+    bytes: u64,
+    duration_ms: f64,
+    page: &'a str,
+    user_agent: Option<&'a str>,
+    referer: Option<&'a str>,
+}
+
+impl AccessLog {
+    pub fn new(config: &Configuration) -> Result<Self> {
+        let output = if config.access_log == AccessLogFormat::Off {
+            None
+        } else {
+            let file = if let Some(path) = &config.access_log_file {
+                let file = RotatingFile::open(
+                    path.clone(),
+                    config.access_log_max_bytes,
+                    config.access_log_max_files,
+                )?;
+                Some(file)
+            } else {
+                None
+            };
+            let (sender, receiver) = sync_channel(MAX_QUEUED_LINES);
+            thread::Builder::new()
+                .name(String::from("access-log"))
+                .spawn(move || write_lines(receiver, file))
+                .context("Failed to start access log writer")?;
+            Some(sender)
+        };
+        Ok(Self {
+            format: config.access_log,
+            sample_rate: config.access_log_sample_rate,
+            excluded_paths: config.access_log_exclude.clone(),
+            trusted_proxies: config.trusted_proxies.clone(),
+            output,
+        })
+    }
+
+    /// Captures the request details, if this request should be logged
+    pub fn start<B>(&self, req: &Request<B>, peer: SocketAddr) -> Option<AccessEntry> {
+        if self.format == AccessLogFormat::Off {
+            return None;
+        }
+        let path = req.uri().path();
+        if self.excluded_paths.iter().any(|p| path.starts_with(p)) {
+            return None;
+        }
+        if self.sample_rate < 1.0 && fastrand::f64() >= self.sample_rate {
+            return None;
+        }
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        Some(AccessEntry {
+            time: Timestamp::now(),
+            client: self.client_ip(req, peer.ip()),
+            method: req.method().to_string(),
+            target: req
+                .uri()
+                .path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or(path.to_string()),
+            version: format!("{:?}", req.version()),
+            user_agent: header(USER_AGENT),
+            referer: header(REFERER),
+        })
+    }
+
+    /// Writes the log line for a finished request
+    pub fn finish(
+        &self,
+        mut entry: AccessEntry,
+        status: u16,
+        bytes: u64,
+        duration: Duration,
+        page: &str,
+    ) {
+        if let Some(target) = redact_secret(&entry.target, page) {
+            entry.target = target;
+        }
+        let line = match self.format {
+            AccessLogFormat::Off => return,
+            AccessLogFormat::Common => entry.common(status, bytes),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                entry.common(status, bytes),
+                escape(entry.referer.as_deref().unwrap_or("-")),
+                escape(entry.user_agent.as_deref().unwrap_or("-"))
+            ),
+            AccessLogFormat::Json => {
+                let json = JsonEntry {
+                    time: entry.time.to_string(),
+                    client_ip: entry.client.to_string(),
+                    method: &entry.method,
+                    path: &entry.target,
+                    protocol: &entry.version,
+                    status,
+                    bytes,
+                    duration_ms: duration.as_secs_f64() * 1000.0,
+                    page,
+                    user_agent: entry.user_agent.as_deref(),
+                    referer: entry.referer.as_deref(),
+                };
+                serde_json::to_string(&json).expect("Failed to serialize access log entry")
+            }
+        };
+
+        if let Some(output) = &self.output
+            && let Err(TrySendError::Full(_)) = output.try_send(line)
+        {
+            warn!("Dropped access log line, the writer can not keep up");
+        }
+    }
+
+    /// Uses the X-Forwarded-For header when the direct peer is a trusted proxy.
+    /// The header is checked from right to left, the first untrusted address is the client.
+    fn client_ip<B>(&self, req: &Request<B>, peer: IpAddr) -> IpAddr {
+        let trusted = |ip: IpAddr| self.trusted_proxies.iter().any(|r| r.contains(ip));
+        if !trusted(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        let values: Vec<_> = req.headers().get_all("X-Forwarded-For").iter().collect();
+        for value in values.into_iter().rev() {
+            let Ok(value) = value.to_str() else {
+                return client;
+            };
+            for ip in value.split(',').rev() {
+                let Ok(ip) = ip.trim().parse::<IpAddr>() else {
+                    return client;
+                };
+                client = ip;
+                if !trusted(ip) {
+                    return client;
+                }
+            }
+        }
+        client
+    }
+}
+
+impl AccessEntry {
+    fn common(&self, status: u16, bytes: u64) -> String {
+        let time = self.time.to_zoned(TimeZone::UTC);
+        format!(
+            "{} - - [{}] \"{} {} {}\" {status} {bytes}",
+            self.client,
+            time.strftime("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            escape(&self.target),
+            self.version
+        )
+    }
+}
+
+/// Replaces the path segment after `{prefix}update/` of a request target with a placeholder,
+/// so that secret update URLs and their job status URLs are not written to the log.
+/// Returns nothing when the target has no such segment.
+fn redact_secret(target: &str, prefix: &str) -> Option<String> {
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    // The prefix is decoded, so the target is decoded the same way as for the page lookup
+    let path = normalize_path(path)?;
+    let secret = path.strip_prefix(prefix)?.strip_prefix("update/")?;
+    let rest = secret.find('/').map(|i| &secret[i..]).unwrap_or("");
+    let mut redacted = encode_path(&format!("{prefix}update/{SECRET_PLACEHOLDER}{rest}"));
+    if let Some(query) = query {
+        redacted.push('?');
+        redacted.push_str(query);
+    }
+    Some(redacted)
+}
+
+/// Writes all received lines to the log file or to stdout without a log file
+fn write_lines(receiver: Receiver<String>, mut file: Option<RotatingFile>) {
+    for line in receiver {
+        if let Some(file) = file.as_mut() {
+            if let Err(err) = file.write_line(&line) {
+                warn!("Failed to write access log: {err:#}");
+            }
+        } else {
+            println!("{line}");
+        }
+    }
+}
+
+/// Log file that is rotated when reaching a maximum size.
+/// Old files get the suffixes .1, .2, ... with .1 being the most recent one.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("Failed to open access log file {}", path.display()))?;
+        let size = file
+            .metadata()
+            .context("Failed to get access log file size")?
+            .len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.max_bytes > 0 && self.size + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}").context("Failed to write to access log file")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path).context("Failed to remove full access log")?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                if rotated(n).exists() {
+                    std::fs::rename(rotated(n), rotated(n + 1))
+                        .context("Failed to rotate access log")?;
+                }
+            }
+            std::fs::rename(&self.path, rotated(1)).context("Failed to rotate access log")?;
+        }
+        *self = Self::open(self.path.clone(), self.max_bytes, self.max_files)?;
+        info!("Rotated access log file {}", self.path.display());
+        Ok(())
+    }
+}
+
+/// Escapes quotes and control characters in quoted log fields
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_update_secrets() {
+        let redact = |target| redact_secret(target, "/docs/");
+        assert_eq!(redact("/docs/update/s3cr3t").unwrap(), "/docs/update/***");
+        assert_eq!(
+            redact("/docs/update/s3cr3t/jobs/12").unwrap(),
+            "/docs/update/***/jobs/12"
+        );
+        assert_eq!(
+            redact("/docs/update/s3cr3t?x=1").unwrap(),
+            "/docs/update/***?x=1"
+        );
+        assert_eq!(
+            redact("/docs//./update/s3%63r3t").unwrap(),
+            "/docs/update/***"
+        );
+        assert_eq!(redact("/docs/update/").unwrap(), "/docs/update/***");
+    }
+
+    #[test]
+    fn keep_other_targets() {
+        let redact = |target| redact_secret(target, "/docs/");
+        assert!(redact("/docs/update").is_none());
+        assert!(redact("/docs/updates/file.html").is_none());
+        assert!(redact("/docs/index.html?update/x").is_none());
+        assert!(redact("/other/update/s3cr3t").is_none());
+        assert!(redact_secret("/docs/update/s3cr3t", "none").is_none());
+    }
+}
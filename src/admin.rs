@@ -0,0 +1,285 @@
+use crate::config::Configuration;
+use crate::health::{Readiness, health_handler};
+use crate::http::{JobCreatedResponse, JobResponse, json_response, serve, text_response};
+use crate::jobs::LastUpdate;
+use crate::metrics::metrics_handler;
+use crate::page::{Page, PageConfig, UpdateContext};
+use crate::pages::{Pages, log_page};
+use anyhow::{Context, ensure};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{ALLOW, AUTHORIZATION, HeaderValue, LOCATION, WWW_AUTHENTICATE};
+use hyper::{Method, Request, Response};
+use serde::Serialize;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tracing::info;
+
+/// Maximum accepted size of page configurations sent to the admin API
+const MAX_CONFIG_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct PageStatus {
+    name: String,
+    prefix: String,
+    repo: String,
+    git_ref: Option<String>,
+    subfolder: Option<String>,
+    commit: Option<String>,
+    paused: bool,
+    last_update: Option<LastUpdate>,
+    files: usize,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    path: String,
+    media_type: String,
+    hash: String,
+    size: usize,
+}
+
+/// Runs the separate admin HTTP server on an already bound listener
+pub async fn start_admin_server(
+    listener: TcpListener,
+    config: Arc<Configuration>,
+    pages: Arc<Pages>,
+    context: Arc<UpdateContext>,
+    readiness: Arc<Readiness>,
+) {
+    serve(listener, move |req, _| {
+        let config = config.clone();
+        let pages = pages.clone();
+        let context = context.clone();
+        let readiness = readiness.clone();
+        async move {
+            let path = req.uri().path().to_string();
+            if let Some(response) = health_handler(&path, &readiness).await {
+                return Ok(response);
+            }
+            if path == "/metrics" {
+                return Ok(metrics_handler(&pages, &context.blobs).await);
+            }
+            if let Some(token) = &config.admin_token
+                && let Some(api_path) = path.strip_prefix("/api/")
+            {
+                let authorized = req
+                    .headers()
+                    .get(AUTHORIZATION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "))
+                    .is_some_and(|v| v.as_bytes().ct_eq(token.as_bytes()).into());
+                if !authorized {
+                    let mut response = text_response(401, "Unauthorized");
+                    response
+                        .headers_mut()
+                        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                    return Ok(response);
+                }
+                return Ok(api_handler(req, api_path, &pages, context).await);
+            }
+            Ok(text_response(404, "Not found"))
+        }
+    })
+    .await
+}
+
+/// Handles all authenticated requests below /api/
+async fn api_handler(
+    req: Request<Incoming>,
+    path: &str,
+    pages: &Pages,
+    context: Arc<UpdateContext>,
+) -> Response<Full<Bytes>> {
+    let method = req.method().clone();
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    let (name, action) = match segments.as_slice() {
+        ["pages"] => {
+            if method != Method::GET {
+                return method_not_allowed(&[Method::GET]);
+            }
+            let mut list = Vec::new();
+            for page_lock in pages.snapshot().iter() {
+                list.push(page_status(&*page_lock.read().await));
+            }
+            return json_response(200, &list);
+        }
+        ["pages", name, action @ ..] => (*name, action),
+        _ => return text_response(404, "Not found"),
+    };
+
+    // Check action and method before looking up the page
+    let allowed: &[Method] = match action {
+        [] => &[Method::GET, Method::PUT, Method::DELETE],
+        ["files"] | ["jobs", _] => &[Method::GET],
+        ["update"] | ["pause"] | ["resume"] => &[Method::POST],
+        _ => return text_response(404, "Not found"),
+    };
+    if !allowed.contains(&method) {
+        return method_not_allowed(allowed);
+    }
+    if method == Method::PUT {
+        return put_page_handler(req, name, pages).await;
+    }
+    if method == Method::DELETE {
+        return delete_page_handler(name, pages).await;
+    }
+    let Some(page_lock) = pages.find_page_by_name(name).await else {
+        return text_response(404, "Page not found");
+    };
+
+    let page = page_lock.read().await;
+    match action {
+        [] => json_response(200, &page_status(&page)),
+        ["files"] => {
+            let files: Vec<_> = page
+                .files
+                .iter()
+                .map(|file| ManifestFile {
+                    path: file.path.clone(),
+                    media_type: file.media_type.clone(),
+                    hash: file.hash.clone(),
+                    size: file.blob.size(),
+                })
+                .collect();
+            json_response(200, &files)
+        }
+        ["jobs", job] => {
+            let status = job.parse().ok().and_then(|job| {
+                let status = page.jobs.status(job)?;
+                Some(JobResponse { job, status })
+            });
+            match status {
+                Some(status) => json_response(200, &status),
+                None => text_response(404, "Job not found"),
+            }
+        }
+        ["update"] => {
+            let jobs = page.jobs.clone();
+            drop(page);
+            let job = jobs.trigger(page_lock.clone(), context, "admin update");
+            let status_url = format!("/api/pages/{name}/jobs/{job}");
+            let mut response = json_response(
+                202,
+                &JobCreatedResponse {
+                    job,
+                    status_url: Some(status_url.clone()),
+                },
+            );
+            if let Ok(value) = HeaderValue::from_str(&status_url) {
+                response.headers_mut().insert(LOCATION, value);
+            }
+            response
+        }
+        ["pause"] | ["resume"] => {
+            let paused = action == ["pause"];
+            page.jobs.set_paused(paused);
+            info!(page = %page.config.prefix, paused, "Changed automatic updates via admin API");
+            json_response(200, &page_status(&page))
+        }
+        _ => text_response(404, "Not found"),
+    }
+}
+
+/// Adds a new page or replaces the configuration of an existing page
+async fn put_page_handler(
+    req: Request<Incoming>,
+    name: &str,
+    pages: &Pages,
+) -> Response<Full<Bytes>> {
+    let body = match Limited::new(req.into_body(), MAX_CONFIG_BYTES)
+        .collect()
+        .await
+    {
+        Ok(collected) => collected.to_bytes(),
+        Err(_) => return text_response(400, "Invalid request body"),
+    };
+    let config = serde_json::from_slice(&body)
+        .context("Failed to parse JSON")
+        .and_then(|json| PageConfig::from_json(name, &json));
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => return error_response(400, &err),
+    };
+
+    let new_config = config.clone();
+    let result = pages
+        .change(|configs| {
+            if let Some(existing) = configs.iter_mut().find(|c| c.name == new_config.name) {
+                *existing = new_config;
+            } else {
+                configs.push(new_config);
+            }
+            Ok(())
+        })
+        .await;
+    let changes = match result {
+        Ok(changes) => changes,
+        Err(err) => return error_response(409, &err),
+    };
+    if !changes.added.is_empty() {
+        info!("Page {name} was added via admin API");
+        log_page(&config);
+    } else if !changes.changed.is_empty() {
+        info!("Page {name} was reconfigured via admin API");
+        log_page(&config);
+    }
+
+    let Some(page_lock) = pages.find_page_by_name(name).await else {
+        return text_response(404, "Page not found");
+    };
+    let status = if changes.added.is_empty() { 200 } else { 201 };
+    json_response(status, &page_status(&*page_lock.read().await))
+}
+
+async fn delete_page_handler(name: &str, pages: &Pages) -> Response<Full<Bytes>> {
+    let result = pages
+        .change(|configs| {
+            let count = configs.len();
+            configs.retain(|c| c.name != name);
+            ensure!(configs.len() < count, "Page not found");
+            Ok(())
+        })
+        .await;
+    match result {
+        Ok(_) => {
+            info!("Page {name} was removed via admin API");
+            text_response(200, "Page removed")
+        }
+        Err(_) => text_response(404, "Page not found"),
+    }
+}
+
+fn page_status(page: &Page) -> PageStatus {
+    PageStatus {
+        name: page.config.name.clone(),
+        prefix: page.config.prefix.clone(),
+        repo: page.config.redacted_repo(),
+        git_ref: page.config.git_ref.clone(),
+        subfolder: page.config.subfolder.clone(),
+        commit: page.last_hash.clone(),
+        paused: page.jobs.is_paused(),
+        last_update: page.jobs.last_update(),
+        files: page.files.len(),
+        bytes: page.files.iter().map(|f| f.blob.size()).sum(),
+    }
+}
+
+fn method_not_allowed(allowed: &[Method]) -> Response<Full<Bytes>> {
+    let mut response = text_response(405, "Method not allowed");
+    let allowed: Vec<_> = allowed.iter().map(|m| m.as_str()).collect();
+    if let Ok(value) = HeaderValue::from_str(&allowed.join(", ")) {
+        response.headers_mut().insert(ALLOW, value);
+    }
+    response
+}
+
+fn error_response(status: u16, error: &anyhow::Error) -> Response<Full<Bytes>> {
+    let body = Full::new(Bytes::from(format!("{error:#}")));
+    Response::builder()
+        .status(status)
+        .body(body)
+        .expect("Failed to build HTTP response")
+}
@@ -0,0 +1,506 @@
+use crate::page::{PageFile, PageStorage};
+use anyhow::{Context, Result, ensure};
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context as TaskContext, Poll, ready};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, ReadBuf};
+use tracing::warn;
+
+/// Larger files of pages with disk storage are never cached and always streamed from disk
+const MAX_CACHED_BLOB_BYTES: usize = 1024 * 1024;
+
+/// Size of the chunks in which files are streamed from disk
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Immutable file contents shared by all pages with the same git object
+pub struct Blob {
+    size: usize,
+    data: BlobData,
+}
+
+enum BlobData {
+    Memory(Bytes),
+    // Content-addressed file in the storage folder, deleted when no longer used
+    Disk(PathBuf),
+}
+
+impl Blob {
+    pub fn memory(data: Bytes) -> Self {
+        Self {
+            size: data.len(),
+            data: BlobData::Memory(data),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Path of the file with the contents, if the blob is stored on disk
+    pub fn path(&self) -> Option<&Path> {
+        match &self.data {
+            BlobData::Memory(_) => None,
+            BlobData::Disk(path) => Some(path),
+        }
+    }
+
+    /// Reads the contents synchronously, only to be used outside of request handling
+    pub fn read_blocking(&self) -> Result<Bytes> {
+        match &self.data {
+            BlobData::Memory(data) => Ok(data.clone()),
+            BlobData::Disk(path) => fs::read(path)
+                .map(Bytes::from)
+                .context(format!("Failed to read blob {}", path.display())),
+        }
+    }
+}
+
+/// Where the contents of new blobs are kept during a checkout
+#[derive(Clone)]
+pub enum BlobTarget {
+    Memory,
+    Disk(Arc<BlobStore>),
+}
+
+impl BlobTarget {
+    pub fn create(&self, hash: &str, data: Vec<u8>) -> Result<Arc<Blob>> {
+        match self {
+            Self::Memory => Ok(Arc::new(Blob::memory(Bytes::from(data)))),
+            Self::Disk(store) => store.store_on_disk(hash, &data),
+        }
+    }
+}
+
+/// Global content-addressed store for the file contents of all pages.
+/// Blobs are keyed by their git object ID and only weakly referenced by the store,
+/// so they are freed as soon as no page uses them anymore.
+/// Blobs of pages with disk storage are kept as files and read through an LRU cache.
+pub struct BlobStore {
+    // Memory and disk blobs with the same ID are stored separately
+    blobs: Mutex<HashMap<(String, PageStorage), Weak<Blob>>>,
+    max_bytes: Option<u64>,
+    folder: PathBuf,
+    // Serializes moving and deleting blob files, so that a file that was just
+    // stored again is never deleted by the garbage collection of its old entry.
+    // Always locked before the blobs and never the other way around.
+    files: Mutex<()>,
+    next_temp: AtomicU64,
+    hot: Mutex<HotCache>,
+}
+
+/// Number and total size of all blobs currently in use
+#[derive(Default)]
+pub struct BlobUsage {
+    pub memory_blobs: usize,
+    pub memory_bytes: u64,
+    pub disk_blobs: usize,
+    pub disk_bytes: u64,
+    pub cache_bytes: u64,
+}
+
+impl BlobStore {
+    pub fn new(max_bytes: Option<u64>, folder: &Path, cache_bytes: usize) -> Self {
+        Self {
+            blobs: Mutex::new(HashMap::new()),
+            max_bytes,
+            folder: folder.to_path_buf(),
+            files: Mutex::new(()),
+            next_temp: AtomicU64::new(0),
+            hot: Mutex::new(HotCache::new(cache_bytes)),
+        }
+    }
+
+    /// Deletes blob files left behind by a previous run,
+    /// except for the files of blobs that were already restored from the disk cache
+    pub fn clean_folder(&self) {
+        let Ok(folders) = fs::read_dir(&self.folder) else {
+            return;
+        };
+        for folder in folders.flatten() {
+            // Only touch the two character blob folders
+            let name = folder.file_name().to_string_lossy().to_string();
+            if name.len() != 2 || !name.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            let Ok(blobs) = fs::read_dir(folder.path()) else {
+                continue;
+            };
+            for blob in blobs.flatten() {
+                let hash = format!("{name}{}", blob.file_name().to_string_lossy());
+                if self.stored(&(hash, PageStorage::Disk)).is_some() {
+                    continue;
+                }
+                if let Err(err) = fs::remove_file(blob.path()) {
+                    warn!("Failed to delete old blob {}: {err}", blob.path().display());
+                }
+            }
+        }
+    }
+
+    /// Replaces the contents of the new files with already stored blobs of the same object ID
+    /// and adds all other blobs to the store. Files of pages with disk storage were already
+    /// stored when they were created. Fails if the optional memory limit would be exceeded
+    /// after the files of the replaced page are released.
+    pub fn intern<'a>(
+        &self,
+        files: Vec<PageFile>,
+        replaced: impl Iterator<Item = &'a PageFile>,
+        storage: PageStorage,
+    ) -> Result<Vec<PageFile>> {
+        if storage == PageStorage::Disk {
+            return Ok(files);
+        }
+
+        let mut blobs = self.blobs.lock().expect("Failed to lock blob store");
+        let key = |hash: &str| (hash.to_string(), PageStorage::Memory);
+
+        if let Some(max) = self.max_bytes {
+            let mut added = HashSet::new();
+            let mut added_bytes = 0;
+            for file in &files {
+                let stored = blobs
+                    .get(&key(&file.hash))
+                    .is_some_and(|b| b.strong_count() > 0);
+                if !stored && added.insert(&file.hash) {
+                    added_bytes += file.blob.size as u64;
+                }
+            }
+
+            // Blobs only used by the replaced page are freed after the update.
+            // The page can reference the same blob from multiple files.
+            let new_hashes: HashSet<_> = files.iter().map(|f| &f.hash).collect();
+            let mut page_references = HashMap::new();
+            for file in replaced {
+                if matches!(file.blob.data, BlobData::Memory(_)) && !new_hashes.contains(&file.hash)
+                {
+                    page_references
+                        .entry(&file.hash)
+                        .or_insert((&file.blob, 0))
+                        .1 += 1;
+                }
+            }
+            let released_bytes: u64 = page_references
+                .values()
+                .filter(|(blob, count)| Arc::strong_count(blob) == *count)
+                .map(|(blob, _)| blob.size as u64)
+                .sum();
+
+            let bytes = usage(&blobs).memory_bytes + added_bytes;
+            let bytes = bytes.saturating_sub(released_bytes);
+            ensure!(
+                bytes <= max,
+                "Files of all pages would need {bytes} bytes, which is over the global limit of {max} bytes"
+            );
+        }
+
+        let mut interned = Vec::with_capacity(files.len());
+        for mut file in files {
+            if let Some(blob) = blobs.get(&key(&file.hash)).and_then(Weak::upgrade) {
+                file.blob = blob;
+            } else {
+                blobs.insert(key(&file.hash), Arc::downgrade(&file.blob));
+            }
+            interned.push(file);
+        }
+        Ok(interned)
+    }
+
+    /// Writes the contents to the storage folder, unless the blob is already stored there.
+    /// The file is written without holding any lock to a temporary file first.
+    pub fn store_on_disk(&self, hash: &str, data: &[u8]) -> Result<Arc<Blob>> {
+        self.add_file(hash, data.len(), |temp_path| {
+            fs::write(temp_path, data)
+                .context(format!("Failed to write blob {}", temp_path.display()))
+        })
+    }
+
+    /// Adds a blob file from the disk cache to the storage folder without copying it.
+    /// A file with the expected size that is left from the previous run is used as it is,
+    /// otherwise the file is hard linked and only copied if that is not possible.
+    pub fn link_on_disk(&self, hash: &str, source: &Path, size: usize) -> Result<Arc<Blob>> {
+        let path = self.blob_path(hash)?;
+        if fs::metadata(&path).is_ok_and(|m| m.len() == size as u64) {
+            let _files = self.files.lock().expect("Failed to lock blob files");
+            return Ok(self.insert_disk_blob(hash, path, size));
+        }
+        self.add_file(hash, size, |temp_path| link_or_copy(source, temp_path))
+    }
+
+    /// Creates a disk blob with a file written to a temporary path by the closure,
+    /// unless the blob is already stored
+    fn add_file(
+        &self,
+        hash: &str,
+        size: usize,
+        write: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<Arc<Blob>> {
+        let key = (hash.to_string(), PageStorage::Disk);
+        if let Some(blob) = self.stored(&key) {
+            return Ok(blob);
+        }
+
+        let path = self.blob_path(hash)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!(
+                "Failed to create storage folder {}",
+                parent.display()
+            ))?;
+        }
+        // Pages can store the same blob at the same time
+        let temp = self.next_temp.fetch_add(1, Ordering::Relaxed);
+        let temp_path = path.with_extension(format!("{temp}.tmp"));
+        write(&temp_path)?;
+
+        let _files = self.files.lock().expect("Failed to lock blob files");
+        if let Some(blob) = self.stored(&key) {
+            if let Err(err) = fs::remove_file(&temp_path) {
+                warn!("Failed to delete blob {}: {err}", temp_path.display());
+            }
+            return Ok(blob);
+        }
+        fs::rename(&temp_path, &path)
+            .context(format!("Failed to move blob to {}", path.display()))?;
+        Ok(self.insert_disk_blob(hash, path, size))
+    }
+
+    /// Adds the entry of a blob file, the file lock has to be held by the caller
+    fn insert_disk_blob(&self, hash: &str, path: PathBuf, size: usize) -> Arc<Blob> {
+        let key = (hash.to_string(), PageStorage::Disk);
+        let mut blobs = self.blobs.lock().expect("Failed to lock blob store");
+        if let Some(blob) = blobs.get(&key).and_then(Weak::upgrade) {
+            return blob;
+        }
+        let blob = Arc::new(Blob {
+            size,
+            data: BlobData::Disk(path),
+        });
+        blobs.insert(key, Arc::downgrade(&blob));
+        blob
+    }
+
+    /// Returns the contents of a blob for a response.
+    /// Small disk blobs are read through the LRU cache, all others are streamed from disk.
+    pub async fn read(&self, hash: &str, blob: &Blob) -> Result<BlobContents> {
+        let path = match &blob.data {
+            BlobData::Memory(data) => return Ok(BlobContents::Bytes(data.clone())),
+            BlobData::Disk(path) => path,
+        };
+        let cached = {
+            let mut hot = self.hot.lock().expect("Failed to lock cache");
+            if let Some(data) = hot.get(hash) {
+                return Ok(BlobContents::Bytes(data));
+            }
+            blob.size <= MAX_CACHED_BLOB_BYTES && blob.size <= hot.max_bytes
+        };
+
+        if cached {
+            let data = tokio::fs::read(path)
+                .await
+                .map(Bytes::from)
+                .context(format!("Failed to read blob {}", path.display()))?;
+            let mut hot = self.hot.lock().expect("Failed to lock cache");
+            hot.insert(hash, data.clone());
+            Ok(BlobContents::Bytes(data))
+        } else {
+            let file = File::open(path)
+                .await
+                .context(format!("Failed to open blob {}", path.display()))?;
+            Ok(BlobContents::File(FileBody::new(file, blob.size as u64)))
+        }
+    }
+
+    pub fn usage(&self) -> BlobUsage {
+        let blobs = self.blobs.lock().expect("Failed to lock blob store");
+        let mut usage = usage(&blobs);
+        usage.cache_bytes = self.hot.lock().expect("Failed to lock cache").bytes as u64;
+        usage
+    }
+
+    /// Removes entries of freed blobs and deletes their files.
+    /// Blocks while deleting files, so it must not be called from async code.
+    pub fn collect_garbage(&self) {
+        let mut freed = Vec::new();
+        let mut blobs = self.blobs.lock().expect("Failed to lock blob store");
+        blobs.retain(|(hash, storage), blob| {
+            let used = blob.strong_count() > 0;
+            if !used && *storage == PageStorage::Disk {
+                freed.push(hash.clone());
+            }
+            used
+        });
+        drop(blobs);
+
+        let _files = self.files.lock().expect("Failed to lock blob files");
+        for hash in freed {
+            // Skip blobs that were stored again in the meantime
+            let key = (hash, PageStorage::Disk);
+            if self.stored(&key).is_some() {
+                continue;
+            }
+            let Ok(path) = self.blob_path(&key.0) else {
+                continue;
+            };
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Failed to delete unused blob {}: {err}", path.display());
+            }
+        }
+    }
+
+    fn stored(&self, key: &(String, PageStorage)) -> Option<Arc<Blob>> {
+        let blobs = self.blobs.lock().expect("Failed to lock blob store");
+        blobs.get(key).and_then(Weak::upgrade)
+    }
+
+    fn blob_path(&self, hash: &str) -> Result<PathBuf> {
+        ensure!(
+            hash.len() > 2 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+            "Invalid blob hash {hash}"
+        );
+        let (folder, file) = hash.split_at(2);
+        Ok(self.folder.join(folder).join(file))
+    }
+}
+
+/// Hard links a blob file to avoid a second copy of the contents on disk.
+/// Falls back to copying, for example if both paths are on different file systems.
+pub fn link_or_copy(source: &Path, target: &Path) -> Result<()> {
+    if fs::hard_link(source, target).is_ok() {
+        return Ok(());
+    }
+    fs::copy(source, target).context(format!(
+        "Failed to copy blob {} to {}",
+        source.display(),
+        target.display()
+    ))?;
+    Ok(())
+}
+
+fn usage(blobs: &HashMap<(String, PageStorage), Weak<Blob>>) -> BlobUsage {
+    let mut usage = BlobUsage::default();
+    for blob in blobs.values().filter_map(Weak::upgrade) {
+        match blob.data {
+            BlobData::Memory(_) => {
+                usage.memory_blobs += 1;
+                usage.memory_bytes += blob.size as u64;
+            }
+            BlobData::Disk(_) => {
+                usage.disk_blobs += 1;
+                usage.disk_bytes += blob.size as u64;
+            }
+        }
+    }
+    usage
+}
+
+/// Contents of a blob for a response
+pub enum BlobContents {
+    Bytes(Bytes),
+    File(FileBody),
+}
+
+/// Response body that streams a blob file from disk in chunks
+pub struct FileBody {
+    file: File,
+    remaining: u64,
+    buffer: Vec<u8>,
+}
+
+impl FileBody {
+    fn new(file: File, size: u64) -> Self {
+        Self {
+            file,
+            remaining: size,
+            buffer: vec![0; CHUNK_BYTES],
+        }
+    }
+}
+
+impl Body for FileBody {
+    type Data = Bytes;
+    type Error = Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Error>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let this = &mut *self;
+        let len = this.buffer.len().min(this.remaining as usize);
+        let mut buffer = ReadBuf::new(&mut this.buffer[..len]);
+        ready!(Pin::new(&mut this.file).poll_read(cx, &mut buffer))?;
+        let read = buffer.filled().len();
+        if read == 0 {
+            let error = Error::new(ErrorKind::UnexpectedEof, "Blob file is too short");
+            return Poll::Ready(Some(Err(error)));
+        }
+        let data = Bytes::copy_from_slice(buffer.filled());
+        this.remaining -= read as u64;
+        Poll::Ready(Some(Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+/// Least recently used contents of disk blobs, limited to a total size in bytes
+struct HotCache {
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<String, (Bytes, u64)>,
+    // Hashes ordered by their last use
+    order: BTreeMap<u64, String>,
+}
+
+impl HotCache {
+    fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, hash: &str) -> Option<Bytes> {
+        let (data, tick) = self.entries.get_mut(hash)?;
+        self.order.remove(tick);
+        self.tick += 1;
+        *tick = self.tick;
+        self.order.insert(self.tick, hash.to_string());
+        Some(data.clone())
+    }
+
+    fn insert(&mut self, hash: &str, data: Bytes) {
+        if data.len() > self.max_bytes || self.entries.contains_key(hash) {
+            return;
+        }
+        while self.bytes + data.len() > self.max_bytes {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.len();
+            }
+        }
+        self.tick += 1;
+        self.bytes += data.len();
+        self.order.insert(self.tick, hash.to_string());
+        self.entries.insert(hash.to_string(), (data, self.tick));
+    }
+}
@@ -0,0 +1,305 @@
+use crate::blobs::{BlobTarget, link_or_copy};
+use crate::git::blob_matches;
+use crate::media_type::media_type_from_path;
+use crate::page::{PageConfig, PageFile};
+use anyhow::{Context, Result, ensure};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Persists the last successful snapshot of each page on disk.
+/// File contents are stored as content-addressed blobs using the git object ID.
+/// A JSON manifest per page references the blobs and the commit hash.
+pub struct DiskCache {
+    folder: PathBuf,
+    // Serializes all cache operations to avoid garbage collecting blobs
+    // that were just written for a manifest that is not yet stored.
+    lock: Mutex<()>,
+}
+
+pub struct CachedPage {
+    pub commit: String,
+    pub files: Vec<PageFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    source: String,
+    commit: String,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile {
+    path: String,
+    hash: String,
+    // Seconds since the Unix epoch, missing in older caches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<i64>,
+    // Missing in older caches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+impl DiskCache {
+    pub fn new(folder: &Path) -> Result<Self> {
+        let cache = Self {
+            folder: folder.to_path_buf(),
+            lock: Mutex::new(()),
+        };
+        fs::create_dir_all(cache.blobs_folder()).context(format!(
+            "Failed to create cache blob folder in {}",
+            folder.display()
+        ))?;
+        fs::create_dir_all(cache.manifests_folder()).context(format!(
+            "Failed to create cache manifest folder in {}",
+            folder.display()
+        ))?;
+        Ok(cache)
+    }
+
+    /// Stores the files of a page and removes blobs that are no longer referenced
+    pub fn store(&self, config: &PageConfig, commit: &str, files: &[PageFile]) -> Result<()> {
+        let _guard = self.lock.lock().expect("Failed to lock cache");
+
+        let mut manifest = Manifest {
+            source: source_fingerprint(config),
+            commit: commit.to_string(),
+            files: Vec::new(),
+        };
+        for file in files {
+            let blob_path = self.blob_path(&file.hash)?;
+            if !blob_path.exists() {
+                // Files of pages with disk storage are shared with the storage folder
+                match file.blob.path() {
+                    Some(path) => link_atomic(path, &blob_path)?,
+                    None => write_atomic(&blob_path, &file.blob.read_blocking()?)?,
+                }
+            }
+            let path = file
+                .path
+                .strip_prefix(&config.prefix)
+                .context("File path does not start with page prefix")?;
+            manifest.files.push(ManifestFile {
+                path: path.to_string(),
+                hash: file.hash.clone(),
+                modified: file.modified.map(|time| time.as_second()),
+                size: Some(file.blob.size() as u64),
+            });
+        }
+
+        let json = serde_json::to_vec(&manifest).context("Failed to serialize manifest")?;
+        write_atomic(&self.manifest_path(&config.prefix), &json)?;
+
+        self.collect_garbage()
+    }
+
+    /// Removes the cache entry of a page that no longer exists
+    pub fn remove(&self, prefix: &str) -> Result<()> {
+        let _guard = self.lock.lock().expect("Failed to lock cache");
+
+        let manifest_path = self.manifest_path(prefix);
+        if manifest_path.exists() {
+            fs::remove_file(&manifest_path).context(format!(
+                "Failed to delete cache manifest {}",
+                manifest_path.display()
+            ))?;
+        }
+
+        self.collect_garbage()
+    }
+
+    /// Loads the cached files of a page.
+    /// Returns nothing if there is no cache entry or if it was created for different page settings.
+    pub fn load(&self, config: &PageConfig, target: &BlobTarget) -> Result<Option<CachedPage>> {
+        let _guard = self.lock.lock().expect("Failed to lock cache");
+
+        let manifest_path = self.manifest_path(&config.prefix);
+        if !manifest_path.exists() {
+            return Ok(None);
+        }
+        let json = fs::read(&manifest_path).context(format!(
+            "Failed to read cache manifest {}",
+            manifest_path.display()
+        ))?;
+        let manifest: Manifest =
+            serde_json::from_slice(&json).context("Failed to parse cache manifest")?;
+        if manifest.source != source_fingerprint(config) {
+            return Ok(None);
+        }
+
+        let mut files = Vec::new();
+        for file in manifest.files {
+            let blob_path = self.blob_path(&file.hash)?;
+            let blob = match (target, file.size) {
+                // Pages with disk storage share the files with the cache instead of copying them.
+                // Reading them completely to verify the hash is avoided for large pages.
+                (BlobTarget::Disk(store), Some(size)) => {
+                    let metadata = fs::metadata(&blob_path).context(format!(
+                        "Failed to read cached blob {}",
+                        blob_path.display()
+                    ))?;
+                    ensure!(
+                        metadata.len() == size,
+                        "Cached blob {} is corrupted",
+                        blob_path.display()
+                    );
+                    store.link_on_disk(&file.hash, &blob_path, size as usize)?
+                }
+                _ => {
+                    let data = fs::read(&blob_path).context(format!(
+                        "Failed to read cached blob {}",
+                        blob_path.display()
+                    ))?;
+                    ensure!(
+                        blob_matches(&data, &file.hash)?,
+                        "Cached blob {} is corrupted",
+                        blob_path.display()
+                    );
+                    target.create(&file.hash, data)?
+                }
+            };
+            files.push(PageFile {
+                path: format!("{}{}", config.prefix, file.path),
+                media_type: media_type_from_path(&file.path).to_string(),
+                hash: file.hash,
+                blob,
+                modified: file.modified.and_then(|s| Timestamp::from_second(s).ok()),
+            });
+        }
+
+        Ok(Some(CachedPage {
+            commit: manifest.commit,
+            files,
+        }))
+    }
+
+    fn collect_garbage(&self) -> Result<()> {
+        // Collect all blobs referenced by any manifest
+        let mut referenced = HashSet::new();
+        let manifests =
+            fs::read_dir(self.manifests_folder()).context("Failed to list cache manifests")?;
+        for entry in manifests {
+            let path = entry
+                .context("Failed to read manifest folder entry")?
+                .path();
+            let manifest = fs::read(&path)
+                .ok()
+                .and_then(|json| serde_json::from_slice::<Manifest>(&json).ok());
+            if let Some(manifest) = manifest {
+                referenced.extend(manifest.files.into_iter().map(|f| f.hash));
+            } else {
+                warn!("Ignoring invalid cache manifest {}", path.display());
+            }
+        }
+
+        // Delete all other blobs
+        let folders = fs::read_dir(self.blobs_folder()).context("Failed to list cache blobs")?;
+        for folder in folders {
+            let folder = folder.context("Failed to read blob folder entry")?.path();
+            let blobs = fs::read_dir(&folder).context("Failed to list cache blobs")?;
+            for blob in blobs {
+                let blob = blob.context("Failed to read blob folder entry")?;
+                let hash = format!(
+                    "{}{}",
+                    folder.file_name().unwrap_or_default().to_string_lossy(),
+                    blob.file_name().to_string_lossy()
+                );
+                if !referenced.contains(&hash)
+                    && let Err(err) = fs::remove_file(blob.path())
+                {
+                    warn!("Failed to delete unused cache blob {hash}: {err}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn blobs_folder(&self) -> PathBuf {
+        self.folder.join("blobs")
+    }
+
+    fn manifests_folder(&self) -> PathBuf {
+        self.folder.join("pages")
+    }
+
+    fn blob_path(&self, hash: &str) -> Result<PathBuf> {
+        ensure!(
+            hash.len() > 2 && hash.chars().all(|c| c.is_ascii_hexdigit()),
+            "Invalid blob hash {hash}"
+        );
+        let (folder, file) = hash.split_at(2);
+        Ok(self.blobs_folder().join(folder).join(file))
+    }
+
+    /// Named after a hash of the prefix, since prefixes can not be mapped to unique file names
+    fn manifest_path(&self, prefix: &str) -> PathBuf {
+        let name = sha256_hex([prefix]);
+        self.manifests_folder().join(format!("{name}.json"))
+    }
+}
+
+/// Identifies the page settings that influence the page contents, including the file limits
+/// and the git depth used for the modification times of the files.
+/// Hashed to avoid storing any credentials from the repository URL on disk.
+fn source_fingerprint(config: &PageConfig) -> String {
+    let git_depth = config.git_depth.to_string();
+    let limits = format!("{:?}", config.file_limits());
+    sha256_hex([
+        config.repo.as_str(),
+        config.git_ref.as_deref().unwrap_or_default(),
+        config.subfolder.as_deref().unwrap_or_default(),
+        config.prefix.as_str(),
+        &git_depth,
+        &limits,
+    ])
+}
+
+/// Hex encoded SHA-256 hash of all values separated by null bytes
+fn sha256_hex<'a>(values: impl IntoIterator<Item = &'a str>) -> String {
+    let mut hasher = Sha256::new();
+    for value in values {
+        hasher.update(value);
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Links or copies to a temporary file first to never leave partially written files behind
+fn link_atomic(source: &Path, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(format!(
+            "Failed to create cache folder {}",
+            parent.display()
+        ))?;
+    }
+    let temp_path = path.with_extension("tmp");
+    link_or_copy(source, &temp_path)?;
+    fs::rename(&temp_path, path).context(format!("Failed to move cache file to {}", path.display()))
+}
+
+/// Writes to a temporary file first to never leave partially written files behind
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context(format!(
+            "Failed to create cache folder {}",
+            parent.display()
+        ))?;
+    }
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, data).context(format!(
+        "Failed to write cache file {}",
+        temp_path.display()
+    ))?;
+    fs::rename(&temp_path, path).context(format!("Failed to move cache file to {}", path.display()))
+}
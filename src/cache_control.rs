@@ -0,0 +1,162 @@
+use anyhow::{Context, Result, bail, ensure};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Cache-Control header values for files matching a pattern, the first matching rule wins.
+/// Written as `pattern=value` separated by semicolons, like
+/// `*.[hash].js=public, max-age=31536000, immutable; *.html=no-cache`.
+/// Patterns without slash match the file name, others the path relative to the page prefix.
+/// `*` matches any number of characters, `?` a single character
+/// and `[hash]` a fingerprint of at least 8 hexadecimal digits.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheControlRules {
+    rules: Vec<CacheControlRule>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct CacheControlRule {
+    pattern: String,
+    value: String,
+}
+
+/// Minimum length of a fingerprint matched by `[hash]`
+const MIN_HASH_LEN: usize = 8;
+
+impl CacheControlRules {
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Header value of the first rule matching the path relative to the page prefix
+    pub fn find(&self, path: &str) -> Option<&str> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .find(|rule| {
+                let text = if rule.pattern.contains('/') {
+                    path
+                } else {
+                    name
+                };
+                matches(&rule.pattern, text)
+            })
+            .map(|rule| rule.value.as_str())
+    }
+}
+
+impl FromStr for CacheControlRules {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for rule in value.split(';').map(str::trim).filter(|r| !r.is_empty()) {
+            let (pattern, value) = rule
+                .split_once('=')
+                .context(format!("Invalid rule '{rule}', expected pattern=value"))?;
+            let pattern = pattern.trim().trim_start_matches('/').to_string();
+            let value = value.trim().to_string();
+            ensure!(!pattern.is_empty(), "Rule '{rule}' has an empty pattern");
+            ensure!(!value.is_empty(), "Rule '{rule}' has an empty value");
+            if value.chars().any(|c| c.is_control()) {
+                bail!("Rule '{rule}' has a value with control characters");
+            }
+            rules.push(CacheControlRule { pattern, value });
+        }
+        Ok(Self { rules })
+    }
+}
+
+impl Display for CacheControlRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| format!("{}={}", rule.pattern, rule.value))
+            .collect();
+        f.write_str(&rules.join("; "))
+    }
+}
+
+/// Matches a pattern with wildcards against the whole text
+fn matches(pattern: &str, text: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix("[hash]") {
+        let hex = text.bytes().take_while(|c| c.is_ascii_hexdigit()).count();
+        return (MIN_HASH_LEN..=hex).any(|len| matches(rest, &text[len..]));
+    }
+    let mut pattern_chars = pattern.chars();
+    match pattern_chars.next() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = pattern_chars.as_str();
+            let mut positions = text.char_indices().map(|(i, _)| i).chain([text.len()]);
+            positions.any(|skip| matches(rest, &text[skip..]))
+        }
+        Some('?') => {
+            let mut text_chars = text.chars();
+            text_chars.next().is_some() && matches(pattern_chars.as_str(), text_chars.as_str())
+        }
+        Some(c) => text
+            .strip_prefix(c)
+            .is_some_and(|text| matches(pattern_chars.as_str(), text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_patterns() {
+        assert!(matches("index.html", "index.html"));
+        assert!(!matches("index.html", "index.htm"));
+        assert!(!matches("index.htm", "index.html"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.html", "index.html"));
+        assert!(matches("*.html", ".html"));
+        assert!(!matches("*.html", "index.htm"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXbYc"));
+        assert!(matches("img/*", "img/a/b.png"));
+        assert!(matches("?.js", "a.js"));
+        assert!(!matches("?.js", ".js"));
+        assert!(matches("??.js", "äö.js"));
+    }
+
+    #[test]
+    fn hash_placeholder() {
+        assert!(matches("*.[hash].js", "app.0123abcd.js"));
+        assert!(matches("*.[hash].js", "app.0123456789abcdef.js"));
+        assert!(matches("app-[hash].css", "app-DEADBEEF.css"));
+        assert!(!matches("*.[hash].js", "app.0123abc.js"));
+        assert!(!matches("*.[hash].js", "app.0123abcg.js"));
+        assert!(!matches("*.[hash].js", "app.js"));
+        assert!(matches("[hash]", "0123abcd"));
+        assert!(!matches("[hash]", "0123abcdx"));
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules: CacheControlRules = "*.[hash].js=immutable; *.js=no-cache; docs/*=private"
+            .parse()
+            .unwrap();
+        assert_eq!(rules.find("app.0123abcd.js"), Some("immutable"));
+        assert_eq!(rules.find("js/app.js"), Some("no-cache"));
+        assert_eq!(rules.find("docs/a.txt"), Some("private"));
+        assert_eq!(rules.find("a/docs/a.txt"), None);
+        assert_eq!(rules.find("index.html"), None);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules: CacheControlRules = " /a/*=x ; *.html = no-cache, private ;".parse().unwrap();
+        assert_eq!(rules.to_string(), "a/*=x; *.html=no-cache, private");
+        assert!("".parse::<CacheControlRules>().unwrap().is_empty());
+        assert!("*.html".parse::<CacheControlRules>().is_err());
+        assert!("=no-cache".parse::<CacheControlRules>().is_err());
+        assert!("*.html=".parse::<CacheControlRules>().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::blobs::BlobTarget;
+use crate::config::Configuration;
+use crate::files::PageFiles;
+use crate::media_type::DEFAULT_TYPE;
+use crate::page::{INDEX_FILES, PageConfig, checkout_page};
+use crate::pages::Pages;
+use std::collections::BTreeSet;
+use std::path::Path;
+use tracing::{error, info, warn};
+
+/// Number of problems and warnings found by a check
+#[derive(Default)]
+struct Findings {
+    problems: usize,
+    warnings: usize,
+}
+
+/// Checks out all pages once and reports everything that would fail or look wrong at runtime.
+/// Returns false if problems were found, or warnings in strict mode.
+pub async fn check_pages(config: &Configuration, pages: &Pages, strict: bool) -> bool {
+    // Use a separate temp folder to not interfere with a running instance.
+    // Page clones are named after their prefix, the dot avoids using the clone of a page /check/.
+    let temp_folder = config.temp_folder.join(".check");
+    let mut findings = Findings::default();
+    for page_lock in pages.snapshot().iter() {
+        let page_config = page_lock.read().await.config.clone();
+        let page_findings = check_page(page_config, &temp_folder).await;
+        findings.problems += page_findings.problems;
+        findings.warnings += page_findings.warnings;
+    }
+    if temp_folder.exists()
+        && let Err(err) = std::fs::remove_dir_all(&temp_folder)
+    {
+        warn!(
+            "Failed to remove check temp folder {}: {err}",
+            temp_folder.display()
+        );
+    }
+
+    let Findings { problems, warnings } = findings;
+    if problems > 0 {
+        error!(problems, warnings, "Check failed");
+        false
+    } else if warnings > 0 && strict {
+        error!(problems, warnings, "Check failed in strict mode");
+        false
+    } else {
+        info!(problems, warnings, "Check passed");
+        true
+    }
+}
+
+async fn check_page(mut config: PageConfig, temp_folder: &Path) -> Findings {
+    let mut findings = Findings::default();
+    let name = config.name.clone();
+
+    // Check out without size limit to be able to report the full size,
+    // files over the other limits fail the checkout or are reported as skipped
+    let max_bytes = config.max_bytes.take();
+    let checkout = match checkout_page(&config, temp_folder, BlobTarget::Memory).await {
+        Ok(checkout) => checkout,
+        Err(err) => {
+            error!(
+                page = %name,
+                repo = %config.redacted_repo(),
+                error = format!("{err:#}"),
+                "Failed to check out page"
+            );
+            findings.problems += 1;
+            return findings;
+        }
+    };
+    let files = PageFiles::new(checkout.files);
+    let bytes: u64 = files.iter().map(|f| f.blob.size() as u64).sum();
+    info!(
+        page = %name,
+        commit = %checkout.commit,
+        files = files.len(),
+        bytes,
+        "Checked out page"
+    );
+
+    for file in &checkout.skipped {
+        warn!(page = %name, path = %file.path, reason = %file.reason, "File would be skipped");
+        findings.warnings += 1;
+    }
+
+    if let Some(max) = max_bytes
+        && bytes > max
+    {
+        error!(page = %name, bytes, max_bytes = max, "Page is bigger than its size limit");
+        findings.problems += 1;
+    }
+
+    // Folders without index file are not found when there is no folder listing
+    if config.auto_index && !config.auto_list {
+        let folders: BTreeSet<&str> = files
+            .folders()
+            .filter(|folder| folder.starts_with(&config.prefix))
+            .collect();
+        for folder in folders {
+            let has_index = INDEX_FILES
+                .iter()
+                .any(|index| files.get(&format!("{folder}{index}")).is_some());
+            if has_index {
+                continue;
+            }
+            if folder == config.prefix {
+                error!(page = %name, folder, "Page has no index file");
+                findings.problems += 1;
+            } else {
+                warn!(page = %name, folder, "Folder has no index file");
+                findings.warnings += 1;
+            }
+        }
+    }
+
+    for file in files.iter() {
+        if file.media_type == DEFAULT_TYPE {
+            warn!(page = %name, path = %file.path, "File has an unknown media type");
+            findings.warnings += 1;
+        }
+    }
+
+    findings
+}
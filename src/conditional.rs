@@ -0,0 +1,324 @@
+use hyper::header::{
+    HeaderName, HeaderValue, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE,
+};
+use hyper::{HeaderMap, Method};
+use jiff::Timestamp;
+use jiff::fmt::rfc2822::{DateTimeParser, DateTimePrinter};
+use jiff::fmt::strtime;
+use jiff::tz::TimeZone;
+
+/// Outcome of the conditional request headers for an existing file
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+/// Quoted strong entity tag for the git object ID of a file
+pub fn entity_tag(hash: &str) -> String {
+    format!("\"{hash}\"")
+}
+
+/// Formats a time as HTTP date like "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn http_date(time: Timestamp) -> String {
+    DateTimePrinter::new()
+        .timestamp_to_rfc9110_string(&time)
+        .unwrap_or_default()
+}
+
+/// Evaluates the preconditions of a request in the order defined by RFC 9110 section 13.2.2.
+/// The entity tag of the file is the git object ID without quotes.
+pub fn evaluate(
+    method: &Method,
+    headers: &HeaderMap,
+    hash: &str,
+    modified: Option<Timestamp>,
+) -> Precondition {
+    let get_or_head = method == Method::GET || method == Method::HEAD;
+
+    if headers.contains_key(IF_MATCH) {
+        if !any_tag_matches(headers.get_all(IF_MATCH), hash, false) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = header_date(headers, IF_UNMODIFIED_SINCE)
+        && let Some(modified) = modified
+        && modified > since
+    {
+        return Precondition::Failed;
+    }
+
+    if headers.contains_key(IF_NONE_MATCH) {
+        if any_tag_matches(headers.get_all(IF_NONE_MATCH), hash, true) {
+            return if get_or_head {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if get_or_head
+        && let Some(since) = header_date(headers, IF_MODIFIED_SINCE)
+        && let Some(modified) = modified
+        && modified <= since
+    {
+        return Precondition::NotModified;
+    }
+
+    Precondition::Proceed
+}
+
+/// True if any header value is `*` or a list containing the entity tag.
+/// Weak comparison ignores the `W/` prefix, strong comparison never matches weak tags.
+fn any_tag_matches<'a>(
+    values: impl IntoIterator<Item = &'a HeaderValue>,
+    hash: &str,
+    weak_comparison: bool,
+) -> bool {
+    values
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| tag_list_matches(value, hash, weak_comparison))
+}
+
+fn tag_list_matches(value: &str, hash: &str, weak_comparison: bool) -> bool {
+    let mut rest = value.trim();
+    if rest == "*" {
+        return true;
+    }
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return false;
+        }
+        let weak = rest.starts_with("W/");
+        if weak {
+            rest = &rest[2..];
+        }
+        // Entity tags are quoted and may contain commas but no quotes
+        let Some(quoted) = rest.strip_prefix('"') else {
+            return false;
+        };
+        let Some(end) = quoted.find('"') else {
+            return false;
+        };
+        if &quoted[..end] == hash && (weak_comparison || !weak) {
+            return true;
+        }
+        rest = &quoted[end + 1..];
+    }
+}
+
+/// Parses an HTTP date header, invalid dates are ignored as required by the spec
+fn header_date(headers: &HeaderMap, name: HeaderName) -> Option<Timestamp> {
+    let value = headers.get(name)?.to_str().ok()?;
+    parse_http_date(value)
+}
+
+/// Parses the preferred format like "Sun, 06 Nov 1994 08:49:37 GMT" and the obsolete formats
+/// "Sunday, 06-Nov-94 08:49:37 GMT" (RFC 850) and "Sun Nov  6 08:49:37 1994" (asctime)
+fn parse_http_date(value: &str) -> Option<Timestamp> {
+    if let Ok(time) = DateTimeParser::new().parse_timestamp(value) {
+        return Some(time);
+    }
+    let (format, value) = match value.strip_suffix(" GMT") {
+        Some(value) => ("%A, %d-%b-%y %H:%M:%S", value),
+        None => ("%a %b %e %H:%M:%S %Y", value),
+    };
+    let time = strtime::parse(format, value).ok()?.to_datetime().ok()?;
+    TimeZone::UTC.to_timestamp(time).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123abcd";
+
+    fn headers(values: &[(HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn time(value: &str) -> Timestamp {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn strong_and_weak_comparison() {
+        assert!(tag_list_matches("\"0123abcd\"", HASH, false));
+        assert!(tag_list_matches("\"0123abcd\"", HASH, true));
+        assert!(!tag_list_matches("W/\"0123abcd\"", HASH, false));
+        assert!(tag_list_matches("W/\"0123abcd\"", HASH, true));
+        assert!(!tag_list_matches("\"0123abce\"", HASH, true));
+        assert!(!tag_list_matches("0123abcd", HASH, true));
+    }
+
+    #[test]
+    fn tag_lists() {
+        assert!(tag_list_matches("\"a\", \"0123abcd\"", HASH, false));
+        assert!(tag_list_matches("\"a\",W/\"0123abcd\"", HASH, true));
+        assert!(tag_list_matches(" , \"a\" ,\t\"0123abcd\" ", HASH, false));
+        assert!(!tag_list_matches("\"a\", \"b\"", HASH, true));
+        assert!(!tag_list_matches("", HASH, true));
+    }
+
+    #[test]
+    fn wildcard() {
+        assert!(tag_list_matches("*", HASH, false));
+        assert!(tag_list_matches(" * ", HASH, true));
+        assert!(!tag_list_matches("\"*\"", HASH, true));
+    }
+
+    #[test]
+    fn commas_inside_quotes() {
+        assert!(tag_list_matches("\"a,b\", \"0123abcd\"", HASH, false));
+        assert!(!tag_list_matches("\"a,\"0123abcd\"", HASH, false));
+        assert!(tag_list_matches(
+            "\"0123abcd,x\", \"0123abcd\"",
+            HASH,
+            false
+        ));
+    }
+
+    #[test]
+    fn multiple_header_values() {
+        let headers = headers(&[(IF_NONE_MATCH, "\"a\""), (IF_NONE_MATCH, "\"0123abcd\"")]);
+        assert!(any_tag_matches(headers.get_all(IF_NONE_MATCH), HASH, true));
+    }
+
+    #[test]
+    fn date_formats() {
+        let expected = time("1994-11-06T08:49:37Z");
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(expected)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(expected));
+        assert_eq!(parse_http_date("yesterday"), None);
+        assert_eq!(http_date(expected), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn unconditional_requests() {
+        let modified = Some(time("2024-01-01T00:00:00Z"));
+        let result = evaluate(&Method::GET, &HeaderMap::new(), HASH, modified);
+        assert_eq!(result, Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match() {
+        let matching = headers(&[(IF_MATCH, "\"0123abcd\"")]);
+        let weak = headers(&[(IF_MATCH, "W/\"0123abcd\"")]);
+        assert_eq!(
+            evaluate(&Method::GET, &matching, HASH, None),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(&Method::GET, &weak, HASH, None),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        let modified = Some(time("2024-01-01T00:00:00Z"));
+        let headers = headers(&[
+            (IF_MATCH, "\"0123abcd\""),
+            (IF_UNMODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+        assert_eq!(
+            evaluate(&Method::GET, &headers, HASH, modified),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        let modified = Some(time("2024-01-01T00:00:00Z"));
+        let before = headers(&[(IF_UNMODIFIED_SINCE, "Sun, 31 Dec 2023 00:00:00 GMT")]);
+        let after = headers(&[(IF_UNMODIFIED_SINCE, "Tue, 02 Jan 2024 00:00:00 GMT")]);
+        let invalid = headers(&[(IF_UNMODIFIED_SINCE, "invalid")]);
+        assert_eq!(
+            evaluate(&Method::GET, &before, HASH, modified),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate(&Method::GET, &after, HASH, modified),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(&Method::GET, &invalid, HASH, modified),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_none_match() {
+        let headers = headers(&[(IF_NONE_MATCH, "W/\"0123abcd\"")]);
+        assert_eq!(
+            evaluate(&Method::GET, &headers, HASH, None),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&Method::HEAD, &headers, HASH, None),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&Method::POST, &headers, HASH, None),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let modified = Some(time("2024-01-01T00:00:00Z"));
+        let headers = headers(&[
+            (IF_NONE_MATCH, "\"other\""),
+            (IF_MODIFIED_SINCE, "Tue, 02 Jan 2024 00:00:00 GMT"),
+        ]);
+        assert_eq!(
+            evaluate(&Method::GET, &headers, HASH, modified),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let modified = Some(time("2024-01-01T00:00:00Z"));
+        let same = headers(&[(IF_MODIFIED_SINCE, "Mon, 01 Jan 2024 00:00:00 GMT")]);
+        let before = headers(&[(IF_MODIFIED_SINCE, "Sun, 31 Dec 2023 00:00:00 GMT")]);
+        assert_eq!(
+            evaluate(&Method::GET, &same, HASH, modified),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            evaluate(&Method::GET, &before, HASH, modified),
+            Precondition::Proceed
+        );
+        // Only used for GET and HEAD requests and for files with a known time
+        assert_eq!(
+            evaluate(&Method::POST, &same, HASH, modified),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            evaluate(&Method::GET, &same, HASH, None),
+            Precondition::Proceed
+        );
+    }
+
+    #[test]
+    fn failed_if_match_takes_precedence_over_if_none_match() {
+        let headers = headers(&[(IF_MATCH, "\"other\""), (IF_NONE_MATCH, "\"0123abcd\"")]);
+        assert_eq!(
+            evaluate(&Method::GET, &headers, HASH, None),
+            Precondition::Failed
+        );
+    }
+}
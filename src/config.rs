@@ -1,10 +1,31 @@
-use clap::Parser;
+use crate::access_log::{AccessLogFormat, IpRange};
+use crate::config_file::ConfigFile;
+use crate::page::is_page_arg;
+use anyhow::{Result, ensure};
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
 use std::{net::IpAddr, path::PathBuf};
 use tracing::{Level, info};
 
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
 #[derive(Parser, Clone)]
-#[command(version, about, long_about = None, ignore_errors(true))]
+#[command(version, about, long_about = None)]
 pub struct Configuration {
+    /// Optional TOML or YAML file with global settings and pages.
+    /// Command line arguments and environment variables override the values from the file.
+    #[arg(long, env)]
+    pub config_file: Option<PathBuf>,
+
+    /// Seconds between checks of the config file for changes (0 is only reload on SIGHUP)
+    #[arg(long, env, default_value_t = 5)]
+    pub config_watch_interval: u64,
+
     /// HTTP server port
     #[arg(long, env, default_value_t = 8080)]
     pub http_port: u16,
@@ -13,25 +34,236 @@ pub struct Configuration {
     #[arg(long, env, default_value = "0.0.0.0")]
     pub http_binding: IpAddr,
 
+    /// Optional port for a separate admin HTTP server.
+    /// When set, the health endpoints are served there instead of the main HTTP server.
+    #[arg(long, env)]
+    pub admin_port: Option<u16>,
+
+    /// Admin HTTP server binding address
+    #[arg(long, env, default_value = "0.0.0.0")]
+    pub admin_binding: IpAddr,
+
+    /// Bearer token required for the admin API on the admin port.
+    /// The admin API is disabled without a token.
+    #[arg(long, env)]
+    pub admin_token: Option<String>,
+
+    /// Respond with 503 until all required pages have content
+    #[arg(long, env, default_value_t = false, action = ArgAction::Set)]
+    pub wait_for_ready: bool,
+
+    /// Comma separated prefixes of pages required for readiness (default is all pages)
+    #[arg(long, env, value_delimiter = ',')]
+    pub ready_pages: Vec<String>,
+
+    /// Seconds after startup when the application is considered ready anyway (0 is no timeout)
+    #[arg(long, env, default_value_t = 0)]
+    pub ready_timeout: u64,
+
     /// Temporary folder used to store bare git checkouts druing updates
     #[arg(long, env, default_value = "./temp")]
     pub temp_folder: PathBuf,
 
+    /// Optional folder to persist the last successful snapshot of each page.
+    /// Cached pages are served immediately after startup.
+    #[arg(long, env)]
+    pub cache_folder: Option<PathBuf>,
+
+    /// Optional limit in bytes for the files of all pages together.
+    /// Files shared by multiple pages are only counted once.
+    /// Updates that would exceed the limit fail.
+    #[arg(long, env)]
+    pub max_memory_bytes: Option<u64>,
+
+    /// Folder for the file contents of pages with disk storage.
+    /// Must not be shared with other instances, it is cleaned on startup
+    /// when any page uses disk storage.
+    #[arg(long, env, default_value = "./storage")]
+    pub storage_folder: PathBuf,
+
+    /// Size in bytes of the in-memory cache for recently served files of pages with disk storage
+    #[arg(long, env, default_value_t = 64 * 1024 * 1024)]
+    pub storage_cache_bytes: usize,
+
     /// Logging level
     #[arg(long, env, default_value = "INFO")]
     pub log_level: Level,
 
+    /// Optional log filter directives like "info,gix=warn" (overrides the log level)
+    #[arg(long, env)]
+    pub log_filter: Option<String>,
+
+    /// Log output format (text or json)
+    #[arg(long, env, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+
     /// Background update interval for check the git repos in seconds
+    /// (can be overridden per page)
     #[arg(long, env, default_value_t = 300)]
     pub interval: u64,
+
+    /// Maximum random delay in seconds before the first update of each page after startup.
+    /// Pages added or changed while running are updated without delay.
+    #[arg(long, env, default_value_t = 0)]
+    pub startup_jitter: u64,
+
+    /// Access log format (off, common, combined or json)
+    #[arg(long, env, value_enum, default_value = "off")]
+    pub access_log: AccessLogFormat,
+
+    /// Optional file for the access log (default is standard output)
+    #[arg(long, env)]
+    pub access_log_file: Option<PathBuf>,
+
+    /// Size in bytes after which the access log file is rotated (0 is no rotation)
+    #[arg(long, env, default_value_t = 10 * 1024 * 1024)]
+    pub access_log_max_bytes: u64,
+
+    /// Number of rotated access log files to keep
+    #[arg(long, env, default_value_t = 5)]
+    pub access_log_max_files: usize,
+
+    /// Fraction of requests written to the access log between 0 and 1
+    #[arg(long, env, default_value_t = 1.0)]
+    pub access_log_sample_rate: f64,
+
+    /// Comma separated path prefixes excluded from the access log
+    #[arg(long, env, value_delimiter = ',')]
+    pub access_log_exclude: Vec<String>,
+
+    /// Comma separated IP addresses or CIDR ranges of proxies trusted to set X-Forwarded-For
+    #[arg(long, env, value_delimiter = ',')]
+    pub trusted_proxies: Vec<IpRange>,
+
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+/// Optional commands instead of running the server
+#[derive(Subcommand, Clone, Debug)]
+pub enum Action {
+    /// Validates the configuration and checks out all pages once without serving them.
+    /// Exits with an error code if any problems are found.
+    Check {
+        /// Also fail on warnings like missing index files or unknown media types
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Checks out a page once and writes the served files with a manifest
+    /// to a folder or to a .tar.gz archive
+    Export {
+        /// Name of the page to export
+        #[arg(long)]
+        page: String,
+
+        /// Output folder or archive file ending with .tar.gz or .tgz
+        #[arg(long)]
+        out: PathBuf,
+    },
 }
 
 impl Configuration {
+    /// Parses the command line arguments and environment variables and merges them
+    /// with the optional config file. Returns the contents of the config file.
+    pub fn load() -> Result<(Self, Option<ConfigFile>)> {
+        // Page arguments are parsed separately
+        let args: Vec<OsString> = std::env::args_os()
+            .filter(|arg| !is_page_arg(&arg.to_string_lossy()))
+            .collect();
+        let matches = Self::command().get_matches_from(&args);
+        let config = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+        let Some(path) = &config.config_file else {
+            return Ok((config, None));
+        };
+
+        // Insert values from the file before the real arguments,
+        // but only if they are not already set as argument or environment variable
+        let file = ConfigFile::load(path)?;
+        let mut merged_args = args[..1].to_vec();
+        for (id, file_args) in &file.args {
+            if matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            ) {
+                merged_args.extend(file_args.iter().cloned());
+            }
+        }
+        merged_args.extend_from_slice(&args[1..]);
+        Ok((Self::parse_from(merged_args), Some(file)))
+    }
+
+    /// Checks values that cannot be validated by the argument parser
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            (0.0..=1.0).contains(&self.access_log_sample_rate),
+            "Access log sample rate must be between 0 and 1"
+        );
+        Ok(())
+    }
+
     pub fn log(&self) {
+        if let Some(file) = &self.config_file {
+            info!("Config File: {}", file.display());
+            info!("Config Watch Interval: {} sec", self.config_watch_interval);
+        }
         info!("HTTP Port: {}", self.http_port);
         info!("HTTP Binding: {}", self.http_binding);
+        if let Some(port) = self.admin_port {
+            info!("Admin Port: {port}");
+            info!("Admin Binding: {}", self.admin_binding);
+            if self.admin_token.is_some() {
+                info!("Admin API: enabled");
+            } else {
+                info!("Admin API: disabled");
+            }
+        } else {
+            info!("Admin Port: disabled");
+        }
+        info!("Wait For Ready: {}", self.wait_for_ready);
+        if self.ready_pages.is_empty() {
+            info!("Ready Pages: all");
+        } else {
+            info!("Ready Pages: {}", self.ready_pages.join(", "));
+        }
+        info!("Ready Timeout: {} sec", self.ready_timeout);
         info!("Temp Folder: {}", self.temp_folder.display());
-        info!("Log Level: {}", self.log_level);
+        if let Some(folder) = &self.cache_folder {
+            info!("Cache Folder: {}", folder.display());
+        } else {
+            info!("Cache Folder: disabled");
+        }
+        if let Some(max) = self.max_memory_bytes {
+            info!("Max Memory Bytes: {max}");
+        } else {
+            info!("Max Memory Bytes: unlimited");
+        }
+        info!("Storage Folder: {}", self.storage_folder.display());
+        info!("Storage Cache Bytes: {}", self.storage_cache_bytes);
+        if let Some(filter) = &self.log_filter {
+            info!("Log Filter: {filter}");
+        } else {
+            info!("Log Level: {}", self.log_level);
+        }
+        info!("Log Format: {:?}", self.log_format);
         info!("Update Interval: {} sec", self.interval);
+        info!("Startup Jitter: {} sec", self.startup_jitter);
+        if self.access_log == AccessLogFormat::Off {
+            info!("Access Log: disabled");
+        } else {
+            info!("Access Log: {:?}", self.access_log);
+            if let Some(file) = &self.access_log_file {
+                info!("Access Log File: {}", file.display());
+                info!("Access Log Max Bytes: {}", self.access_log_max_bytes);
+                info!("Access Log Max Files: {}", self.access_log_max_files);
+            }
+            info!("Access Log Sample Rate: {}", self.access_log_sample_rate);
+            if !self.access_log_exclude.is_empty() {
+                info!("Access Log Exclude: {}", self.access_log_exclude.join(", "));
+            }
+        }
+        if !self.trusted_proxies.is_empty() {
+            let proxies: Vec<_> = self.trusted_proxies.iter().map(|r| r.to_string()).collect();
+            info!("Trusted Proxies: {}", proxies.join(", "));
+        }
     }
 }
@@ -0,0 +1,133 @@
+use crate::config::Configuration;
+use crate::page::{PageConfig, PageSettings};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use clap::{Command, CommandFactory};
+use serde_json::Value;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Contents of the optional TOML or YAML configuration file.
+/// Global settings use the names of the command line arguments with underscores,
+/// pages are a list of tables with a name and the page settings in lower case.
+pub struct ConfigFile {
+    /// Global settings as command line arguments by argument ID
+    pub args: Vec<(String, Vec<OsString>)>,
+    /// Page settings are only parsed after applying the overrides from
+    /// command line arguments and environment variables
+    pub pages: Vec<PageSettings>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .context(format!("Failed to read config file {}", path.display()))?;
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let value: Value = match extension.as_str() {
+            "toml" => toml::from_str(&text).context("Failed to parse TOML config file")?,
+            "yaml" | "yml" => {
+                serde_norway::from_str(&text).context("Failed to parse YAML config file")?
+            }
+            _ => bail!("Config file needs the extension .toml, .yaml or .yml"),
+        };
+        Self::from_value(value).context(format!("Invalid config file {}", path.display()))
+    }
+
+    fn from_value(value: Value) -> Result<Self> {
+        let Value::Object(mut settings) = value else {
+            bail!("Config file must contain a table of settings");
+        };
+
+        let pages = match settings.remove("pages") {
+            None => Vec::new(),
+            Some(Value::Array(pages)) => pages
+                .into_iter()
+                .enumerate()
+                .map(|(i, page)| parse_page(i, page))
+                .collect::<Result<_>>()?,
+            Some(_) => bail!("Setting 'pages' must be a list of pages"),
+        };
+
+        let command = Configuration::command();
+        let mut args = Vec::new();
+        for (key, value) in settings {
+            let arg = command
+                .get_arguments()
+                .find(|a| a.get_id() == key.as_str() && a.get_long().is_some())
+                .filter(|_| key != "config_file")
+                .context(format!("Unknown setting '{key}'"))?;
+            let long = arg.get_long().unwrap_or_default();
+            let values = match value {
+                Value::Array(items) => items.iter().map(scalar).collect::<Result<Vec<_>>>(),
+                value => scalar(&value).map(|v| vec![v]),
+            }
+            .context(format!("Invalid value for setting '{key}'"))?;
+            ensure!(
+                values.len() == 1 || arg.get_value_delimiter().is_some(),
+                "Setting '{key}' must be a single value"
+            );
+            let arg_values = vec![OsString::from(format!("--{long}={}", values.join(",")))];
+
+            // Validate the values with the same parser as the command line arguments
+            Command::new("gitomato")
+                .no_binary_name(true)
+                .arg(arg.clone())
+                .try_get_matches_from(&arg_values)
+                .map_err(|err| {
+                    let message = err.to_string();
+                    let message = message.lines().next().unwrap_or_default();
+                    anyhow!(
+                        "Invalid setting '{key}': {}",
+                        message.trim_start_matches("error: ")
+                    )
+                })?;
+            args.push((key, arg_values));
+        }
+
+        Ok(Self { args, pages })
+    }
+
+    /// Names of all global settings that differ between both files
+    pub fn changed_globals(&self, other: &ConfigFile) -> Vec<String> {
+        let value = |file: &ConfigFile, key: &str| {
+            file.args
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.clone())
+        };
+        let mut changed = Vec::new();
+        for (key, _) in self.args.iter().chain(&other.args) {
+            if value(self, key) != value(other, key) && !changed.contains(key) {
+                changed.push(key.clone());
+            }
+        }
+        changed
+    }
+}
+
+fn parse_page(index: usize, page: Value) -> Result<PageSettings> {
+    let Value::Object(mut settings) = page else {
+        bail!("Page {} must be a table of settings", index + 1);
+    };
+    let name = match settings.remove("name") {
+        Some(Value::String(name)) => name,
+        Some(_) => bail!("Name of page {} must be a string", index + 1),
+        None => bail!("Page {} needs a name", index + 1),
+    };
+    let mut settings = PageConfig::json_settings(&Value::Object(settings))
+        .context(format!("Invalid page {name}"))?;
+    settings.insert(String::from("NAME"), name);
+    Ok(settings)
+}
+
+fn scalar(value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => bail!("Expected a string, number or boolean"),
+    }
+}
@@ -0,0 +1,389 @@
+use anyhow::{Context, Result, ensure};
+use hyper::HeaderMap;
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, HeaderValue, ORIGIN, VARY,
+};
+use regex::Regex;
+use std::fmt::{Display, Formatter};
+
+/// Cross-origin resource sharing settings of a page
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cors {
+    origins: Vec<CorsOrigin>,
+    methods: Vec<String>,
+    headers: Option<String>,
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+/// Allowed origin, either any, an exact origin like `https://example.com`
+/// or a regular expression prefixed with a tilde like `~https://.*\.example\.com`
+/// that has to match the whole origin
+#[derive(Clone, Debug)]
+enum CorsOrigin {
+    Any,
+    Exact(String),
+    Pattern { source: String, regex: Regex },
+}
+
+impl PartialEq for CorsOrigin {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Any, Self::Any) => true,
+            (Self::Exact(a), Self::Exact(b)) => a == b,
+            (Self::Pattern { source: a, .. }, Self::Pattern { source: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Display for CorsOrigin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => f.write_str("*"),
+            Self::Exact(origin) => f.write_str(origin),
+            Self::Pattern { source, .. } => write!(f, "~{source}"),
+        }
+    }
+}
+
+impl Cors {
+    /// Parses the comma separated lists of origins, methods and headers.
+    /// Commas within groups, repetitions and classes of origin patterns do not separate origins.
+    pub fn new(
+        origins: &str,
+        methods: Option<&str>,
+        headers: Option<&str>,
+        credentials: bool,
+        max_age: Option<u64>,
+    ) -> Result<Self> {
+        let mut parsed_origins = Vec::new();
+        for origin in origin_list(origins) {
+            let origin = if origin == "*" {
+                CorsOrigin::Any
+            } else if let Some(pattern) = origin.strip_prefix('~') {
+                let regex = Regex::new(&format!("^(?:{pattern})$"))
+                    .context(format!("Invalid origin pattern '{pattern}'"))?;
+                CorsOrigin::Pattern {
+                    source: pattern.to_string(),
+                    regex,
+                }
+            } else {
+                ensure!(
+                    origin.starts_with("http://") || origin.starts_with("https://"),
+                    "Invalid origin '{origin}', expected something like https://example.com"
+                );
+                CorsOrigin::Exact(origin.trim_end_matches('/').to_string())
+            };
+            parsed_origins.push(origin);
+        }
+        ensure!(!parsed_origins.is_empty(), "No allowed origins configured");
+        // Reflecting any origin with credentials would let every website read private responses
+        ensure!(
+            !credentials || !parsed_origins.contains(&CorsOrigin::Any),
+            "Credentials can not be allowed for any origin, list the allowed origins instead"
+        );
+
+        let methods: Vec<String> = list(methods.unwrap_or("GET, HEAD"))
+            .map(|method| method.to_uppercase())
+            .collect();
+        let headers: Vec<&str> = list(headers.unwrap_or_default()).collect();
+        let is_token = |name: &str| name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        for method in &methods {
+            // Preflight requests always ask for a specific method
+            ensure!(
+                method != "*",
+                "Methods can not be a wildcard, list the allowed methods instead"
+            );
+            ensure!(is_token(method), "Invalid method '{method}'");
+        }
+        for header in &headers {
+            ensure!(
+                is_token(header) || *header == "*",
+                "Invalid header name '{header}'"
+            );
+        }
+        let headers = (!headers.is_empty()).then(|| headers.join(", "));
+
+        Ok(Self {
+            origins: parsed_origins,
+            methods,
+            headers,
+            credentials,
+            max_age,
+        })
+    }
+
+    /// Headers for responses to requests from another origin
+    pub fn response_headers(&self, request: &HeaderMap) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        self.add_origin(request, &mut headers);
+        headers
+    }
+
+    /// Headers for responses to preflight requests, nothing if the request is no preflight
+    pub fn preflight_headers(&self, request: &HeaderMap) -> Option<HeaderMap> {
+        let method = request.get(ACCESS_CONTROL_REQUEST_METHOD)?.to_str().ok()?;
+        let mut headers = HeaderMap::new();
+        if !self.methods.iter().any(|m| m == method) || !self.add_origin(request, &mut headers) {
+            return Some(headers);
+        }
+
+        let methods = self.methods.join(", ");
+        if let Ok(value) = HeaderValue::from_str(&methods) {
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, value);
+        }
+        // A wildcard is not supported by browsers for requests with credentials
+        let allowed_headers = match self.headers.as_deref() {
+            Some("*") if self.credentials => request.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+            Some(allowed) => HeaderValue::from_str(allowed).ok(),
+            None => None,
+        };
+        if let Some(value) = allowed_headers {
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, value);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+        Some(headers)
+    }
+
+    /// Adds the allowed origin and returns true if the request origin is allowed.
+    /// The response varies by origin unless any origin is allowed.
+    fn add_origin(&self, request: &HeaderMap, headers: &mut HeaderMap) -> bool {
+        let any = self.origins.contains(&CorsOrigin::Any);
+        if !any {
+            headers.insert(VARY, HeaderValue::from_static("Origin"));
+        }
+        let Some(origin) = request.get(ORIGIN) else {
+            return false;
+        };
+        let Ok(origin_str) = origin.to_str() else {
+            return false;
+        };
+        let allowed = self.origins.iter().any(|allowed| match allowed {
+            CorsOrigin::Any => true,
+            CorsOrigin::Exact(exact) => exact == origin_str,
+            CorsOrigin::Pattern { regex, .. } => regex.is_match(origin_str),
+        });
+        if !allowed {
+            return false;
+        }
+        if any {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+        } else {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+        }
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        true
+    }
+}
+
+impl Display for Cors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let origins: Vec<String> = self.origins.iter().map(|o| o.to_string()).collect();
+        write!(
+            f,
+            "origins {}, methods {}",
+            origins.join(", "),
+            self.methods.join(", ")
+        )?;
+        if let Some(headers) = &self.headers {
+            write!(f, ", headers {headers}")?;
+        }
+        if self.credentials {
+            f.write_str(", with credentials")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, ", max age {max_age} sec")?;
+        }
+        Ok(())
+    }
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Splits a list of origins at commas outside of the groups, repetitions like `{1,3}`
+/// and classes of regular expressions
+fn origin_list(value: &str) -> Vec<&str> {
+    let mut origins = Vec::new();
+    let mut start = 0;
+    let mut depth = 0usize;
+    let mut in_class = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            ']' if in_class => in_class = false,
+            _ if in_class => {}
+            '[' => in_class = true,
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                origins.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    origins.push(&value[start..]);
+    origins
+        .into_iter()
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(origin: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+        headers
+    }
+
+    fn allowed_origin(cors: &Cors, origin: &str) -> Option<String> {
+        let headers = cors.response_headers(&request(origin));
+        headers
+            .get(ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn any_origin() {
+        let cors = Cors::new("*", None, None, false, None).unwrap();
+        assert_eq!(allowed_origin(&cors, "https://a.com").unwrap(), "*");
+        let headers = cors.response_headers(&request("https://a.com"));
+        assert!(!headers.contains_key(VARY));
+    }
+
+    #[test]
+    fn any_origin_with_credentials() {
+        assert!(Cors::new("*", None, None, true, None).is_err());
+        assert!(Cors::new("https://a.com, *", None, None, true, None).is_err());
+    }
+
+    #[test]
+    fn exact_origins() {
+        let cors = Cors::new("https://a.com/, http://b.com:8080", None, None, false, None).unwrap();
+        assert_eq!(
+            allowed_origin(&cors, "https://a.com").unwrap(),
+            "https://a.com"
+        );
+        assert_eq!(
+            allowed_origin(&cors, "http://b.com:8080").unwrap(),
+            "http://b.com:8080"
+        );
+        assert_eq!(allowed_origin(&cors, "http://a.com"), None);
+        assert_eq!(allowed_origin(&cors, "https://a.com.evil.net"), None);
+        let headers = cors.response_headers(&request("https://c.com"));
+        assert_eq!(headers[VARY], "Origin");
+        assert!(cors.response_headers(&HeaderMap::new()).contains_key(VARY));
+    }
+
+    #[test]
+    fn pattern_origins_match_whole_origin() {
+        let cors = Cors::new(r"~https://.*\.example\.com", None, None, false, None).unwrap();
+        assert!(allowed_origin(&cors, "https://x.example.com").is_some());
+        assert!(allowed_origin(&cors, "https://x.example.com.attacker.net").is_none());
+        assert!(allowed_origin(&cors, "evil://https://x.example.com").is_none());
+        assert_eq!(
+            cors.to_string(),
+            r"origins ~https://.*\.example\.com, methods GET, HEAD"
+        );
+    }
+
+    #[test]
+    fn pattern_origins_with_commas() {
+        let cors = Cors::new(
+            r"~https://[a-z]{1,3}\.example\.com, ~https://(a|b,c)\.net, https://d.org",
+            None,
+            None,
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(cors.origins.len(), 3);
+        assert!(allowed_origin(&cors, "https://abc.example.com").is_some());
+        assert!(allowed_origin(&cors, "https://abcd.example.com").is_none());
+        assert!(allowed_origin(&cors, "https://b,c.net").is_some());
+        assert!(allowed_origin(&cors, "https://d.org").is_some());
+        assert_eq!(origin_list(r"~a[,{]b, c\,d"), vec![r"~a[,{]b", r"c\,d"]);
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert!(Cors::new("", None, None, false, None).is_err());
+        assert!(Cors::new("example.com", None, None, false, None).is_err());
+        assert!(Cors::new("~https://(", None, None, false, None).is_err());
+        assert!(Cors::new("*", Some("*"), None, false, None).is_err());
+        assert!(Cors::new("*", Some("GET;"), None, false, None).is_err());
+        assert!(Cors::new("*", None, Some("X Header"), false, None).is_err());
+        assert!(Cors::new("*", Some("get, put"), Some("*"), false, None).is_ok());
+    }
+
+    #[test]
+    fn preflight() {
+        let cors = Cors::new(
+            "https://a.com",
+            Some("GET, PUT"),
+            Some("X-A"),
+            false,
+            Some(60),
+        )
+        .unwrap();
+        let mut request = request("https://a.com");
+        assert!(cors.preflight_headers(&request).is_none());
+
+        request.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("PUT"),
+        );
+        let headers = cors.preflight_headers(&request).unwrap();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_ORIGIN], "https://a.com");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "X-A");
+        assert_eq!(headers[ACCESS_CONTROL_MAX_AGE], "60");
+
+        request.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("DELETE"),
+        );
+        let headers = cors.preflight_headers(&request).unwrap();
+        assert!(!headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn preflight_wildcard_headers_with_credentials() {
+        let cors = Cors::new("https://a.com", None, Some("*"), true, None).unwrap();
+        let mut request = request("https://a.com");
+        request.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("GET"),
+        );
+        request.insert(
+            ACCESS_CONTROL_REQUEST_HEADERS,
+            HeaderValue::from_static("x-a, x-b"),
+        );
+        let headers = cors.preflight_headers(&request).unwrap();
+        assert_eq!(headers[ACCESS_CONTROL_ALLOW_HEADERS], "x-a, x-b");
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{Context, Result, ensure};
+use jiff::civil::{Date, Time};
+use jiff::tz::TimeZone;
+use jiff::{Timestamp, ToSpan};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Upper limit of search steps before giving up on finding the next match.
+/// This protects against impossible expressions like "0 0 31 2 *".
+const MAX_SEARCH_STEPS: usize = 100_000;
+
+/// Cron schedule with the classic five fields:
+/// minute, hour, day of month, month and day of week.
+/// Supports lists, ranges and steps like "*/15", "1-5" or "0,30".
+/// All schedules are evaluated in UTC.
+#[derive(Clone, PartialEq)]
+pub struct CronSchedule {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// Find the next point in time after the given timestamp matching this schedule.
+    pub fn next_after(&self, time: Timestamp) -> Option<Timestamp> {
+        let start = time.to_zoned(TimeZone::UTC).datetime();
+        let mut current = start
+            .date()
+            .at(start.hour(), start.minute(), 0, 0)
+            .checked_add(1.minute())
+            .ok()?;
+        for _ in 0..MAX_SEARCH_STEPS {
+            let date = current.date();
+            if !has_bit(self.months, current.month()) {
+                current = date
+                    .first_of_month()
+                    .checked_add(1.month())
+                    .ok()?
+                    .to_datetime(Time::midnight());
+            } else if !self.day_matches(date) {
+                current = date.tomorrow().ok()?.to_datetime(Time::midnight());
+            } else if !has_bit(self.hours, current.hour()) {
+                current = date
+                    .at(current.hour(), 0, 0, 0)
+                    .checked_add(1.hour())
+                    .ok()?;
+            } else if !has_bit(self.minutes, current.minute()) {
+                current = current.checked_add(1.minute()).ok()?;
+            } else {
+                return TimeZone::UTC.to_timestamp(current).ok();
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: Date) -> bool {
+        let day = has_bit(self.days, date.day());
+        let weekday = has_bit(self.weekdays, date.weekday().to_sunday_zero_offset());
+        // Same semantics as the classic cron implementations:
+        // If both day fields are restricted, any of them needs to match.
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        ensure!(
+            fields.len() == 5,
+            "Cron expression '{expression}' must have exactly five fields"
+        );
+        let minutes = parse_field(fields[0], 0, 59).context("Invalid minute field")?;
+        let hours = parse_field(fields[1], 0, 23).context("Invalid hour field")?;
+        let days = parse_field(fields[2], 1, 31).context("Invalid day of month field")?;
+        let months = parse_field(fields[3], 1, 12).context("Invalid month field")?;
+        let mut weekdays = parse_field(fields[4], 0, 7).context("Invalid day of week field")?;
+
+        // Sunday can be written as 0 or 7
+        if has_bit(weekdays, 7) {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day: fields[2].starts_with("*"),
+            any_weekday: fields[4].starts_with("*"),
+        })
+    }
+}
+
+impl Display for CronSchedule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+fn parse_field(field: &str, min: u8, max: u8) -> Result<u64> {
+    let mut bits = 0;
+    for part in field.split(",") {
+        let (range, step) = if let Some((range, step)) = part.split_once("/") {
+            let step: u8 = step
+                .parse()
+                .context(format!("Invalid step value '{step}'"))?;
+            ensure!(step > 0, "Step value must be greater than zero");
+            (range, Some(step))
+        } else {
+            (part, None)
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once("-") {
+            (parse_value(start)?, parse_value(end)?)
+        } else {
+            let value = parse_value(range)?;
+            // A single value with a step means "from value to max"
+            if step.is_some() {
+                (value, max)
+            } else {
+                (value, value)
+            }
+        };
+        ensure!(
+            start >= min && end <= max && start <= end,
+            "Range '{range}' is outside of the allowed values {min}-{max}"
+        );
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str) -> Result<u8> {
+    value.parse().context(format!("Invalid value '{value}'"))
+}
+
+fn has_bit(bits: u64, value: i8) -> bool {
+    (0..64).contains(&value) && bits & (1 << value) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(expression: &str, time: &str) -> Option<String> {
+        let schedule: CronSchedule = expression.parse().unwrap();
+        let time: Timestamp = time.parse().unwrap();
+        schedule.next_after(time).map(|next| next.to_string())
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse_field("*", 0, 59).unwrap(), (1 << 60) - 1);
+        assert_eq!(parse_field("5", 0, 59).unwrap(), 1 << 5);
+        assert_eq!(parse_field("1-3", 0, 59).unwrap(), 0b1110);
+        assert_eq!(parse_field("0,30", 0, 59).unwrap(), 1 | 1 << 30);
+        assert_eq!(parse_field("*/20", 0, 59).unwrap(), 1 | 1 << 20 | 1 << 40);
+        assert_eq!(
+            parse_field("10/20", 0, 59).unwrap(),
+            1 << 10 | 1 << 30 | 1 << 50
+        );
+        assert_eq!(parse_field("1-5/2", 0, 59).unwrap(), 0b101010);
+    }
+
+    #[test]
+    fn reject_invalid_expressions() {
+        for expression in [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "5-1 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "1,,2 * * * *",
+        ] {
+            assert!(expression.parse::<CronSchedule>().is_err(), "{expression}");
+        }
+    }
+
+    #[test]
+    fn sunday_as_seven() {
+        let zero: CronSchedule = "0 0 * * 0".parse().unwrap();
+        let seven: CronSchedule = "0 0 * * 7".parse().unwrap();
+        assert_eq!(zero.weekdays, seven.weekdays);
+    }
+
+    #[test]
+    fn next_execution() {
+        let time = "2024-03-10T12:34:56Z";
+        assert_eq!(next("* * * * *", time).unwrap(), "2024-03-10T12:35:00Z");
+        assert_eq!(next("*/15 * * * *", time).unwrap(), "2024-03-10T12:45:00Z");
+        assert_eq!(next("30 2 * * *", time).unwrap(), "2024-03-11T02:30:00Z");
+        assert_eq!(next("0 0 1 * *", time).unwrap(), "2024-04-01T00:00:00Z");
+        assert_eq!(next("0 0 * 1 *", time).unwrap(), "2025-01-01T00:00:00Z");
+        // 2024-03-10 is a Sunday
+        assert_eq!(next("0 9 * * 1-5", time).unwrap(), "2024-03-11T09:00:00Z");
+        assert_eq!(next("0 0 29 2 *", time).unwrap(), "2028-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn next_execution_is_strictly_later() {
+        let time = "2024-03-10T12:35:00Z";
+        assert_eq!(next("35 12 * * *", time).unwrap(), "2024-03-11T12:35:00Z");
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 15th or any Monday, whatever comes first
+        let time = "2024-03-10T12:00:00Z";
+        assert_eq!(next("0 0 15 * 1", time).unwrap(), "2024-03-11T00:00:00Z");
+        let time = "2024-03-12T12:00:00Z";
+        assert_eq!(next("0 0 15 * 1", time).unwrap(), "2024-03-15T00:00:00Z");
+    }
+
+    #[test]
+    fn impossible_schedule() {
+        assert_eq!(next("0 0 31 2 *", "2024-03-10T12:00:00Z"), None);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::blobs::BlobTarget;
+use crate::config::Configuration;
+use crate::page::{PageCheckout, PageConfig, checkout_page};
+use crate::pages::Pages;
+use anyhow::{Context, Result, ensure};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use hyper::body::Bytes;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+
+/// Name of the manifest file written next to the exported files
+const MANIFEST_NAME: &str = "gitomato-manifest.json";
+
+#[derive(Serialize)]
+struct ExportManifest {
+    name: String,
+    repo: String,
+    git_ref: Option<String>,
+    subfolder: Option<String>,
+    prefix: String,
+    commit: String,
+    files: Vec<ExportFile>,
+}
+
+#[derive(Serialize)]
+struct ExportFile {
+    path: String,
+    media_type: String,
+    hash: String,
+    size: usize,
+}
+
+/// Exported file with the time of the last commit that changed it
+struct ExportEntry {
+    path: String,
+    data: Bytes,
+    modified: SystemTime,
+}
+
+/// Checks out a page once and writes all files exactly as they would be served.
+/// File paths are relative to the page prefix. The output is a folder
+/// or a gzip compressed tar archive if the path ends with .tar.gz or .tgz.
+pub async fn export_page(
+    config: &Configuration,
+    pages: &Pages,
+    name: &str,
+    out: &Path,
+) -> Result<()> {
+    let page_lock = pages
+        .find_page_by_name(name)
+        .await
+        .context("Page not found")?;
+    let page_config = page_lock.read().await.config.clone();
+
+    // Use a separate temp folder to not interfere with a running instance.
+    // Page clones are named after their prefix, the dot avoids using the clone of a page /export/.
+    let temp_folder = config.temp_folder.join(".export");
+    let checkout = checkout_page(&page_config, &temp_folder, BlobTarget::Memory).await?;
+    let files = checkout.files.len();
+    let commit = checkout.commit.clone();
+    for file in &checkout.skipped {
+        warn!(page = %name, path = %file.path, reason = %file.reason, "Skipped file over limit");
+    }
+
+    let out = out.to_path_buf();
+    let archive = out
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .is_some_and(|n| n.ends_with(".tar.gz") || n.ends_with(".tgz"));
+    let out_clone = out.clone();
+    let handle = spawn_blocking(move || {
+        let entries = export_entries(&page_config, checkout)?;
+        if archive {
+            write_archive(&out_clone, &entries)
+        } else {
+            write_folder(&out_clone, &entries)
+        }
+    });
+    handle
+        .await
+        .context("Failed to join blocking export task")??;
+
+    info!(
+        page = %name,
+        commit = %commit,
+        files,
+        "Exported page to {}",
+        out.display()
+    );
+    Ok(())
+}
+
+/// Relative paths and contents of all exported files including the manifest.
+/// Files get the same modification times as the Last-Modified header of the server,
+/// the manifest gets the time of the most recent file.
+fn export_entries(config: &PageConfig, checkout: PageCheckout) -> Result<Vec<ExportEntry>> {
+    let mut manifest = ExportManifest {
+        name: config.name.clone(),
+        repo: config.redacted_repo(),
+        git_ref: config.git_ref.clone(),
+        subfolder: config.subfolder.clone(),
+        prefix: config.prefix.clone(),
+        commit: checkout.commit,
+        files: Vec::new(),
+    };
+    let now = SystemTime::now();
+    let mut entries = Vec::new();
+    for file in checkout.files {
+        let path = file
+            .path
+            .strip_prefix(&config.prefix)
+            .context("File path does not start with page prefix")?
+            .to_string();
+        ensure!(
+            path != MANIFEST_NAME,
+            "Page contains a file with the reserved name {MANIFEST_NAME}"
+        );
+        manifest.files.push(ExportFile {
+            path: path.clone(),
+            media_type: file.media_type,
+            hash: file.hash,
+            size: file.blob.size(),
+        });
+        entries.push(ExportEntry {
+            path,
+            data: file.blob.read_blocking()?,
+            modified: file.modified.map(SystemTime::from).unwrap_or(now),
+        });
+    }
+    let json = serde_json::to_vec_pretty(&manifest).context("Failed to serialize manifest")?;
+    entries.push(ExportEntry {
+        path: MANIFEST_NAME.to_string(),
+        data: Bytes::from(json),
+        modified: entries.iter().map(|e| e.modified).max().unwrap_or(now),
+    });
+    Ok(entries)
+}
+
+fn write_folder(folder: &Path, entries: &[ExportEntry]) -> Result<()> {
+    // Refuse to mix the export with existing files
+    if folder.exists() {
+        let mut contents = fs::read_dir(folder)
+            .context(format!("Failed to read output folder {}", folder.display()))?;
+        ensure!(
+            contents.next().is_none(),
+            "Output folder {} is not empty",
+            folder.display()
+        );
+    }
+    for entry in entries {
+        let file_path = folder.join(&entry.path);
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)
+                .context(format!("Failed to create folder {}", parent.display()))?;
+        }
+        let mut file = fs::File::create(&file_path)
+            .context(format!("Failed to create file {}", file_path.display()))?;
+        file.write_all(&entry.data)
+            .and_then(|_| file.set_modified(entry.modified))
+            .context(format!("Failed to write file {}", file_path.display()))?;
+    }
+    Ok(())
+}
+
+fn write_archive(archive: &Path, entries: &[ExportEntry]) -> Result<()> {
+    let file = fs::File::create(archive)
+        .context(format!("Failed to create archive {}", archive.display()))?;
+    let seconds = |time: SystemTime| {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    };
+
+    // Folders get the time of their most recent file and are added before their contents
+    let mut folders = BTreeMap::new();
+    for entry in entries {
+        let mut path = entry.path.as_str();
+        while let Some((parent, _)) = path.rsplit_once('/') {
+            let modified = folders
+                .entry(format!("{parent}/"))
+                .or_insert(entry.modified);
+            *modified = entry.modified.max(*modified);
+            path = parent;
+        }
+    }
+
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for (path, modified) in &folders {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        header.set_mtime(seconds(*modified));
+        builder
+            .append_data(&mut header, path, std::io::empty())
+            .context(format!("Failed to add {path} to archive"))?;
+    }
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(seconds(entry.modified));
+        builder
+            .append_data(&mut header, &entry.path, entry.data.as_ref())
+            .context(format!("Failed to add {} to archive", entry.path))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .context("Failed to finish archive")?;
+    Ok(())
+}
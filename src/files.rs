@@ -0,0 +1,81 @@
+use crate::page::PageFile;
+use std::collections::{BTreeMap, HashMap};
+
+/// Files of a page with an index by path and a folder tree,
+/// so that lookups are constant and listings proportional to the folder size.
+#[derive(Clone, Default)]
+pub struct PageFiles {
+    files: Vec<PageFile>,
+    // Position of each file by path
+    paths: HashMap<String, usize>,
+    // Children of each folder path (with trailing slash) by name.
+    // Subfolders have a trailing slash and no file position.
+    folders: HashMap<String, BTreeMap<String, Option<usize>>>,
+}
+
+impl PageFiles {
+    pub fn new(files: Vec<PageFile>) -> Self {
+        let mut paths = HashMap::with_capacity(files.len());
+        let mut folders: HashMap<String, BTreeMap<String, Option<usize>>> = HashMap::new();
+        for (index, file) in files.iter().enumerate() {
+            paths.insert(file.path.clone(), index);
+
+            // Register the file and all of its parent folders in their parent folder
+            let path = &file.path;
+            let slashes: Vec<usize> = path.match_indices('/').map(|(i, _)| i).collect();
+            for (i, &slash) in slashes.iter().enumerate() {
+                let (child, position) = match slashes.get(i + 1) {
+                    Some(&next) => (&path[slash + 1..=next], None),
+                    None => (&path[slash + 1..], Some(index)),
+                };
+                let folder = &path[..=slash];
+                if let Some(children) = folders.get_mut(folder) {
+                    children.insert(child.to_string(), position);
+                } else {
+                    let children = BTreeMap::from([(child.to_string(), position)]);
+                    folders.insert(folder.to_string(), children);
+                }
+            }
+        }
+        Self {
+            files,
+            paths,
+            folders,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> Option<&PageFile> {
+        self.paths.get(path).map(|&index| &self.files[index])
+    }
+
+    /// True if the path with trailing slash contains any files
+    pub fn is_folder(&self, path: &str) -> bool {
+        self.folders.contains_key(path)
+    }
+
+    /// Paths of all folders with trailing slash
+    pub fn folders(&self) -> impl Iterator<Item = &str> {
+        self.folders.keys().map(|folder| folder.as_str())
+    }
+
+    /// Direct children of a folder sorted by name, subfolders have a trailing slash
+    pub fn children(
+        &self,
+        folder: &str,
+    ) -> Option<impl Iterator<Item = (&str, Option<&PageFile>)>> {
+        let children = self.folders.get(folder)?;
+        Some(
+            children
+                .iter()
+                .map(|(name, index)| (name.as_str(), index.map(|i| &self.files[i]))),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PageFile> {
+        self.files.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+}
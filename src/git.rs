@@ -1,31 +1,94 @@
+use crate::blobs::{Blob, BlobTarget};
 use anyhow::{Context, Result, bail};
+use gix::ObjectId;
 use gix::bstr::BStr;
 use gix::clone::PrepareFetch;
 use gix::create::Kind;
 use gix::progress::Discard;
 use gix::remote::fetch::Shallow;
 use gix::traverse::tree::Recorder;
+use jiff::Timestamp;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::num::NonZero;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
 use tracing::warn;
 
 pub struct GitCheckout {
     pub hash: String,
     pub files: Vec<GitFile>,
+    /// Files left out because of limits with the skip policy
+    pub skipped: Vec<SkippedFile>,
+}
+
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Optional limits for the files of a checkout
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileLimits {
+    pub max_bytes: Option<u64>,
+    pub max_file_bytes: Option<u64>,
+    pub max_file_bytes_policy: LimitPolicy,
+    pub max_files: Option<usize>,
+    pub max_files_policy: LimitPolicy,
+    pub max_path_depth: Option<usize>,
+    pub max_path_depth_policy: LimitPolicy,
+}
+
+/// What happens to files that exceed a limit
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LimitPolicy {
+    /// The whole update fails
+    #[default]
+    Fail,
+    /// The file is left out with a warning
+    Skip,
+}
+
+impl FromStr for LimitPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            _ => bail!("Invalid value '{value}', expected fail or skip"),
+        }
+    }
+}
+
+impl Display for LimitPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+        };
+        f.write_str(value)
+    }
 }
 
 pub struct GitFile {
     pub path: String,
     pub hash: String,
-    pub data: Vec<u8>,
+    pub blob: Arc<Blob>,
+    /// Time of the last commit that changed the file within the fetched history
+    pub modified: Timestamp,
 }
 
 pub fn get_git_files(
     repo: &str,
     refrence: Option<&str>,
     temp_bare_folder: &Path,
-    max_bytes: Option<u64>,
+    depth: NonZero<u32>,
+    subfolder: Option<&str>,
+    limits: &FileLimits,
+    target: &BlobTarget,
 ) -> Result<GitCheckout> {
     if temp_bare_folder.exists() {
         std::fs::remove_dir_all(temp_bare_folder).context(format!(
@@ -41,8 +104,7 @@ pub fn get_git_files(
     // Prepare shallow clone
     let create_opts = gix::create::Options::default();
     let open_opts = gix::open::Options::default();
-    let shallow_clone_depth = NonZero::new(1).context("Depth must be non-zero")?;
-    let shallow = Shallow::DepthAtRemote(shallow_clone_depth);
+    let shallow = Shallow::DepthAtRemote(depth);
     let partial_name = refrence.map(BStr::new);
     let mut prep = PrepareFetch::new(
         repo.to_string(),
@@ -101,24 +163,78 @@ pub fn get_git_files(
     platform
         .breadthfirst(&mut recorder)
         .context("Failed to start tree traversal")?;
+    let time = commit_time(&commit)?;
 
+    // Only files from the subfolder are read, with paths relative to it
     let mut files = Vec::new();
+    let mut full_paths = HashMap::new();
+    let mut skipped = Vec::new();
     let mut bytes_sum = 0;
     for r in recorder.records.iter() {
-        if r.mode.is_blob() {
-            let blob = repo.find_blob(r.oid).context("Failed to find blob")?;
-            let size = blob.data.len() as u64;
-            bytes_sum += size;
-            if let Some(max) = max_bytes
-                && bytes_sum > max
-            {
-                bail!("Files behind commit are bigger than the limit of {max} bytes");
+        if !r.mode.is_blob() {
+            continue;
+        }
+        let full_path = r.filepath.to_string();
+        let path = match subfolder {
+            Some(folder) => match full_path.strip_prefix(folder) {
+                Some(path) => path.to_string(),
+                None => continue,
+            },
+            None => full_path.clone(),
+        };
+
+        if let Some(max) = limits.max_path_depth {
+            let depth = path.split('/').count();
+            if depth > max {
+                let reason = format!("path depth {depth} is over the limit of {max}");
+                enforce(limits.max_path_depth_policy, path, reason, &mut skipped)?;
+                continue;
             }
-            files.push(GitFile {
-                path: r.filepath.to_string(),
-                hash: r.oid.to_string(),
-                data: blob.data.clone(),
-            });
+        }
+        if let Some(max) = limits.max_files
+            && files.len() >= max
+        {
+            let reason = format!("number of files is over the limit of {max}");
+            enforce(limits.max_files_policy, path, reason, &mut skipped)?;
+            continue;
+        }
+        // Check the size before the contents are loaded
+        let size = repo
+            .find_header(r.oid)
+            .context("Failed to find blob header")?
+            .size();
+        if let Some(max) = limits.max_file_bytes
+            && size > max
+        {
+            let reason = format!("size of {size} bytes is over the limit of {max} bytes");
+            enforce(limits.max_file_bytes_policy, path, reason, &mut skipped)?;
+            continue;
+        }
+        bytes_sum += size;
+        if let Some(max) = limits.max_bytes
+            && bytes_sum > max
+        {
+            bail!("Files behind commit are bigger than the limit of {max} bytes");
+        }
+
+        let mut blob = repo.find_blob(r.oid).context("Failed to find blob")?;
+        let hash = r.oid.to_string();
+        let blob = target.create(&hash, std::mem::take(&mut blob.data))?;
+        full_paths.insert(full_path, r.oid);
+        files.push(GitFile {
+            path,
+            hash,
+            blob,
+            modified: time,
+        });
+    }
+
+    // Search the history for the last change of each file
+    let changes = last_changes(&repo, commit.clone(), full_paths)?;
+    for file in &mut files {
+        let full_path = format!("{}{}", subfolder.unwrap_or_default(), file.path);
+        if let Some(modified) = changes.get(&full_path) {
+            file.modified = *modified;
         }
     }
 
@@ -133,5 +249,81 @@ pub fn get_git_files(
     Ok(GitCheckout {
         hash: commit.id().to_string(),
         files,
+        skipped,
     })
 }
+
+/// Finds the time of the last commit that changed each file by following the first parents.
+/// Files without change in a shallow history are missing in the result,
+/// files from the root commit get its time.
+fn last_changes<'repo>(
+    repo: &'repo gix::Repository,
+    mut commit: gix::Commit<'repo>,
+    mut files: HashMap<String, ObjectId>,
+) -> Result<HashMap<String, Timestamp>> {
+    let mut changes = HashMap::new();
+    while !files.is_empty() {
+        let time = commit_time(&commit)?;
+        let Some(parent_id) = commit.parent_ids().next() else {
+            // All remaining files were added by the root commit
+            changes.extend(files.drain().map(|(path, _)| (path, time)));
+            break;
+        };
+        // Parents beyond the depth of a shallow clone are not available
+        let Ok(parent) = repo.find_commit(parent_id) else {
+            break;
+        };
+        let tree = parent
+            .tree()
+            .context("Cannot get tree from parent commit")?;
+        let mut recorder = Recorder::default();
+        tree.traverse()
+            .breadthfirst(&mut recorder)
+            .context("Failed to start tree traversal of parent commit")?;
+        let parent_files: HashMap<String, ObjectId> = recorder
+            .records
+            .into_iter()
+            .filter(|r| r.mode.is_blob())
+            .map(|r| (r.filepath.to_string(), r.oid))
+            .collect();
+        files.retain(|path, oid| {
+            let unchanged = parent_files.get(path) == Some(oid);
+            if !unchanged {
+                changes.insert(path.clone(), time);
+            }
+            unchanged
+        });
+        commit = parent;
+    }
+    Ok(changes)
+}
+
+fn commit_time(commit: &gix::Commit<'_>) -> Result<Timestamp> {
+    let time = commit.time().context("Failed to get commit time")?;
+    Timestamp::from_second(time.seconds).context("Invalid commit time")
+}
+
+/// Fails for the fail policy or records the skipped file
+fn enforce(
+    policy: LimitPolicy,
+    path: String,
+    reason: String,
+    skipped: &mut Vec<SkippedFile>,
+) -> Result<()> {
+    match policy {
+        LimitPolicy::Fail => bail!("File {path} exceeds a limit: {reason}"),
+        LimitPolicy::Skip => {
+            skipped.push(SkippedFile { path, reason });
+            Ok(())
+        }
+    }
+}
+
+/// Checks if the contents match a git object ID, which can be a SHA-1 or SHA-256 hash
+pub fn blob_matches(data: &[u8], hash: &str) -> Result<bool> {
+    let expected =
+        ObjectId::from_hex(hash.as_bytes()).context(format!("Invalid object ID {hash}"))?;
+    let id = gix::objs::compute_hash(expected.kind(), gix::objs::Kind::Blob, data)
+        .context("Failed to calculate blob hash")?;
+    Ok(id == expected)
+}
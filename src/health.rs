@@ -0,0 +1,86 @@
+use crate::config::Configuration;
+use crate::http::text_response;
+use crate::pages::Pages;
+use anyhow::{Result, ensure};
+use http_body_util::Full;
+use hyper::Response;
+use hyper::body::Bytes;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Tracks if the application is ready to serve the configured pages.
+/// Once ready, the application stays ready even if later updates fail,
+/// since the pages keep serving their last successful content.
+pub struct Readiness {
+    pages: Arc<Pages>,
+    required_prefixes: Vec<String>,
+    deadline: Option<Instant>,
+    ready: AtomicBool,
+}
+
+impl Readiness {
+    pub async fn new(pages: Arc<Pages>, config: &Configuration) -> Result<Self> {
+        for prefix in &config.ready_pages {
+            let mut found = false;
+            for page_lock in pages.snapshot().iter() {
+                found |= page_lock.read().await.config.prefix == *prefix;
+            }
+            ensure!(
+                found,
+                "Found no page with prefix {prefix} for readiness check"
+            );
+        }
+        let deadline = (config.ready_timeout > 0)
+            .then(|| Instant::now() + Duration::from_secs(config.ready_timeout));
+        Ok(Self {
+            pages,
+            required_prefixes: config.ready_pages.clone(),
+            deadline,
+            ready: AtomicBool::new(false),
+        })
+    }
+
+    /// Ready means that all required pages (or all pages if not specified)
+    /// have content or that the ready timeout has expired.
+    pub async fn is_ready(&self) -> bool {
+        if self.ready.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        if let Some(deadline) = self.deadline
+            && Instant::now() >= deadline
+        {
+            if !self.ready.swap(true, Ordering::Relaxed) {
+                warn!("Ready timeout expired before all required pages had content");
+            }
+            return true;
+        }
+
+        for page_lock in self.pages.snapshot().iter() {
+            let page = page_lock.read().await;
+            let required = self.required_prefixes.is_empty()
+                || self.required_prefixes.contains(&page.config.prefix);
+            if required && page.last_hash.is_none() {
+                return false;
+            }
+        }
+
+        if !self.ready.swap(true, Ordering::Relaxed) {
+            info!("All required pages have content, application is ready");
+        }
+        true
+    }
+}
+
+/// Handles the liveness and readiness endpoints
+pub async fn health_handler(path: &str, readiness: &Readiness) -> Option<Response<Full<Bytes>>> {
+    match path {
+        "/healthz" => Some(text_response(200, "OK")),
+        "/readyz" if readiness.is_ready().await => Some(text_response(200, "Ready")),
+        "/readyz" => Some(text_response(503, "Not ready")),
+        _ => None,
+    }
+}
@@ -1,53 +1,160 @@
+use crate::access_log::AccessLog;
+use crate::blobs::{BlobContents, BlobStore, FileBody};
+use crate::conditional::{Precondition, entity_tag, evaluate, http_date};
 use crate::config::Configuration;
-use crate::page::{Page, PageFile, update_page};
+use crate::health::{Readiness, health_handler};
+use crate::jobs::JobStatus;
+use crate::metrics::{NO_PAGE, metrics, metrics_handler};
+use crate::page::{Lookup, Page, PageConfig, PageFile, UpdateContext};
 use crate::pages::Pages;
+use crate::url_path::{encode_path, normalize_path};
+use crate::webhook::{is_push_event, parse_push_event, verify_request};
 use anyhow::{Context, Result};
-use http_body_util::Full;
-use hyper::body::{Bytes, Incoming};
-use hyper::header::IF_NONE_MATCH;
+use http_body_util::{BodyExt, Either, Full, Limited};
+use hyper::body::{Body, Bytes, Incoming};
+use hyper::header::{
+    ALLOW, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, HeaderValue, LAST_MODIFIED, LOCATION,
+    RETRY_AFTER,
+};
 use hyper::server::conn::http1::Builder;
 use hyper::service::service_fn;
-use hyper::{Method, Request, Response};
+use hyper::{HeaderMap, Method, Request, Response};
 use hyper_util::rt::TokioIo;
+use serde::Serialize;
 use std::convert::Infallible;
-use std::net::SocketAddr;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::signal::ctrl_c;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, RwLockReadGuard};
 use tokio::time::Instant;
 use tokio::{select, spawn};
-use tracing::{info, warn};
+use tracing::{error, info, warn};
 
-pub async fn start_server(config: Arc<Configuration>, pages: Arc<Pages>) -> Result<()> {
-    let addr = SocketAddr::from((config.http_binding, config.http_port));
-    let listener = TcpListener::bind(addr)
+/// Maximum accepted size of webhook request bodies
+const MAX_WEBHOOK_BYTES: usize = 10 * 1024 * 1024;
+
+#[derive(Serialize)]
+pub struct JobCreatedResponse {
+    pub job: u64,
+    // Only available when the secret update URL is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_url: Option<String>,
+}
+
+/// Body of page responses, files of pages with disk storage are streamed
+pub type PageBody = Either<Full<Bytes>, FileBody>;
+
+/// Methods supported for page files and folder listings
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+#[derive(Serialize)]
+pub struct JobResponse {
+    pub job: u64,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+pub async fn start_server(
+    listener: TcpListener,
+    config: Arc<Configuration>,
+    pages: Arc<Pages>,
+    context: Arc<UpdateContext>,
+    readiness: Arc<Readiness>,
+    access_log: Arc<AccessLog>,
+) {
+    let health_endpoints = config.admin_port.is_none();
+    serve(listener, move |req, client| {
+        let config = config.clone();
+        let pages = pages.clone();
+        let context = context.clone();
+        let readiness = readiness.clone();
+        let access_log = access_log.clone();
+        async move {
+            let path = req.uri().path().to_string();
+            let head = req.method() == Method::HEAD;
+            if health_endpoints {
+                if let Some(response) = health_handler(&path, &readiness).await {
+                    return Ok(response.map(Either::Left));
+                }
+                if path == "/metrics" {
+                    return Ok(metrics_handler(&pages, &context.blobs)
+                        .await
+                        .map(Either::Left));
+                }
+            }
+
+            let access_entry = access_log.start(&req, client);
+            let start = Instant::now();
+            let decoded_path = normalize_path(&path);
+            let (response, prefix) = if config.wait_for_ready && !readiness.is_ready().await {
+                let mut response = text_response(503, "Service starting");
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from_static("5"));
+                (response.map(Either::Left), None)
+            } else if let Some(decoded_path) = &decoded_path {
+                root_handler(req, decoded_path, pages, context).await?
+            } else {
+                (text_response(400, "Invalid path").map(Either::Left), None)
+            };
+            let duration = start.elapsed();
+
+            let page = prefix.unwrap_or_else(|| String::from(NO_PAGE));
+            // Bodies of HEAD responses are never sent
+            let bytes = if head {
+                0
+            } else {
+                response.body().size_hint().exact().unwrap_or_default()
+            };
+            let status = response.status().as_u16();
+            metrics().record_request(&page, status, bytes, duration);
+            if let Some(entry) = access_entry {
+                access_log.finish(entry, status, bytes, duration, &page);
+            }
+
+            Ok(response)
+        }
+    })
+    .await
+}
+
+/// Binds the TCP listener of a server
+pub async fn bind(binding: IpAddr, port: u16) -> Result<TcpListener> {
+    let addr = SocketAddr::from((binding, port));
+    TcpListener::bind(addr)
         .await
-        .context("Failed to bind TCP address")?;
+        .context(format!("Failed to bind TCP address {addr}"))
+}
+
+/// Accepts HTTP connections and passes all requests with the client address to the handler
+/// until the application receives a shutdown signal.
+pub async fn serve<H, F, B>(listener: TcpListener, handler: H)
+where
+    H: Fn(Request<Incoming>, SocketAddr) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = Result<Response<B>, Infallible>> + Send + 'static,
+    B: Body<Data = Bytes, Error: Into<Box<dyn Error + Send + Sync>>> + Send + 'static,
+{
     loop {
-        let stream = select! {
+        let (stream, client) = select! {
             result = listener.accept() => {
-                if let Ok((stream, _)) = result {
-                    stream
+                if let Ok(accepted) = result {
+                    accepted
                 } else {
                     warn!("Failed to accept request");
                     continue;
                 }
             },
-            _ = shutdown_signal() => { return Ok(()); },
+            _ = shutdown_signal() => { return; },
         };
         let io = TokioIo::new(stream);
-        let pages = pages.clone();
-        let config = config.clone();
+        let handler = handler.clone();
         spawn(async move {
             if let Err(err) = Builder::new()
                 .serve_connection(
                     io,
-                    service_fn(|req: Request<Incoming>| {
-                        let pages = pages.clone();
-                        let config = config.clone();
-                        async move { root_handler(req, config, pages).await }
-                    }),
+                    service_fn(|req: Request<Incoming>| handler(req, client)),
                 )
                 .await
             {
@@ -79,106 +186,350 @@ async fn shutdown_signal() {
     }
 }
 
+/// Handles all page requests using the decoded and normalized request path.
+/// Returns the response together with the prefix of the matching page.
 async fn root_handler(
     req: Request<Incoming>,
-    config: Arc<Configuration>,
+    path: &str,
     pages: Arc<Pages>,
-) -> Result<Response<Full<Bytes>>, Infallible> {
-    let uri = req.uri();
-    let path = uri.path();
-    if req.method() == Method::GET
-        && let Some(page_lock) = pages.find_page(path).await
-    {
+    context: Arc<UpdateContext>,
+) -> Result<(Response<PageBody>, Option<String>), Infallible> {
+    let read = req.method() == Method::GET || req.method() == Method::HEAD;
+    let query = req.uri().query().map(|q| q.to_string());
+    let mut prefix = None;
+    if let Some(page_lock) = pages.find_page(path).await {
         let page = page_lock.read().await;
-        if let Some(secret) = &page.update_secret {
-            let update_path = format!("{}update/{}", page.prefix, secret);
-            if path == update_path {
-                let page_prefix = page.prefix.clone();
-                drop(page);
-                return update_handler(config, &page_prefix, page_lock).await;
-            }
-        }
-        if let Some(file) = page.find_file(path) {
-            return file_handler(req, file).await;
+        prefix = Some(page.config.prefix.clone());
+        if let Some(response) = page_handler(req, path, page, &page_lock, context).await? {
+            return Ok((response, prefix));
         }
-        if page.auto_list
-            && path.ends_with("/")
-            && let Some(html) = page.list_folder(path)
+    }
+
+    // Redirect to the prefix of a page when the trailing slash is missing
+    if read && !path.ends_with('/') {
+        let folder = format!("{path}/");
+        if let Some(page_lock) = pages.find_page(&folder).await
+            && page_lock.read().await.config.prefix == folder
         {
-            let bytes = Bytes::from(html);
-            let body = Full::new(bytes);
-            let response = Response::builder()
-                .status(200)
-                .header("Content-Type", "text/html")
-                .body(body)
-                .expect("Failed to build HTTP response");
-            return Ok(response);
+            let response = redirect_response(&folder, query.as_deref()).map(Either::Left);
+            return Ok((response, prefix));
         }
     }
+
     let body = Bytes::from_static(b"Not found");
     let response = Response::builder()
         .status(404)
-        .body(Full::new(body))
+        .body(Either::Left(Full::new(body)))
         .expect("Failed to build HTTP response");
-    Ok(response)
+    Ok((response, prefix))
 }
 
-async fn file_handler(
+/// Handles a request for the page with the matching prefix.
+/// Returns nothing when the page has no file or folder for the path.
+async fn page_handler(
     req: Request<Incoming>,
-    file: &PageFile,
-) -> Result<Response<Full<Bytes>>, Infallible> {
-    // Handle If-None-Match requests for current ETag
-    if let Some(value) = req.headers().get(IF_NONE_MATCH)
-        && let Ok(str_value) = value.to_str()
-        && str_value == file.hash
+    path: &str,
+    page: RwLockReadGuard<'_, Page>,
+    page_lock: &Arc<RwLock<Page>>,
+    context: Arc<UpdateContext>,
+) -> Result<Option<Response<PageBody>>, Infallible> {
+    let method = req.method().clone();
+    if method == Method::POST
+        && page.config.webhook_secret.is_some()
+        && path == format!("{}update", page.config.prefix)
+    {
+        drop(page);
+        let response = webhook_handler(req, context, page_lock).await?;
+        return Ok(Some(response.map(Either::Left)));
+    }
+    let read = method == Method::GET || method == Method::HEAD;
+    if method == Method::GET
+        && let Some(secret) = &page.config.update_secret
     {
-        let bytes = Bytes::new();
-        let body = Full::new(bytes);
+        let update_path = format!("{}update/{}", page.config.prefix, secret);
+        if path == update_path {
+            drop(page);
+            let response = update_handler(context, page_lock, "GET webhook update").await?;
+            return Ok(Some(response.map(Either::Left)));
+        }
+        // Job IDs are easy to guess, so the status is protected by the secret as well
+        let jobs_path = format!("{update_path}/jobs/");
+        if let Some(job) = path.strip_prefix(&jobs_path) {
+            return Ok(Some(job_status_handler(&page, job)?.map(Either::Left)));
+        }
+    }
+    match page.lookup(path) {
+        Lookup::File(_) | Lookup::Redirect(_) if !read => {
+            return Ok(Some(method_response(&req, &page.config).map(Either::Left)));
+        }
+        Lookup::File(file) => {
+            // The response only shares the contents and not the file of the page,
+            // so the file is released together with the page lock before sending.
+            let headers = file_headers(&page.config, file, req.headers());
+            return file_handler(&req, file, headers, &context.blobs)
+                .await
+                .map(Some);
+        }
+        Lookup::Redirect(location) => {
+            let response = redirect_response(&location, req.uri().query());
+            return Ok(Some(response.map(Either::Left)));
+        }
+        Lookup::NotFound => {}
+    }
+    if page.config.auto_list
+        && path.ends_with("/")
+        && let Some(html) = page.list_folder(path)
+    {
+        if !read {
+            return Ok(Some(method_response(&req, &page.config).map(Either::Left)));
+        }
+        let bytes = Bytes::from(html);
+        let body = Either::Left(Full::new(bytes));
         let response = Response::builder()
-            .status(304)
-            .header("ETag", &file.hash)
+            .status(200)
+            .header("Content-Type", "text/html")
             .body(body)
             .expect("Failed to build HTTP response");
+        return Ok(Some(response));
+    }
+    Ok(None)
+}
+
+/// Answers OPTIONS requests for existing resources including CORS preflight requests
+/// and rejects all other methods
+fn method_response(req: &Request<Incoming>, config: &PageConfig) -> Response<Full<Bytes>> {
+    let mut response = if req.method() == Method::OPTIONS {
+        let mut response = text_response(204, "");
+        if let Some(cors) = &config.cors
+            && let Some(headers) = cors.preflight_headers(req.headers())
+        {
+            response.headers_mut().extend(headers);
+        }
+        response
+    } else {
+        text_response(405, "Method not allowed")
+    };
+    response
+        .headers_mut()
+        .insert(ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+    response
+}
+
+/// Caching and CORS headers of a page file that are sent with all responses for it
+fn file_headers(config: &PageConfig, file: &PageFile, request: &HeaderMap) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let etag = entity_tag(&file.hash);
+    headers.insert(ETAG, HeaderValue::from_str(&etag).expect("Invalid ETag"));
+    if let Some(modified) = file.modified {
+        let date = http_date(modified);
+        headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_str(&date).expect("Invalid HTTP date"),
+        );
+    }
+    let relative_path = file.path.strip_prefix(&config.prefix);
+    if let Some(cache_control) = relative_path.and_then(|path| config.cache_control.find(path))
+        && let Ok(value) = HeaderValue::from_str(cache_control)
+    {
+        headers.insert(CACHE_CONTROL, value);
+    }
+    if let Some(cors) = &config.cors {
+        headers.extend(cors.response_headers(request));
+    }
+    headers
+}
+
+async fn file_handler(
+    req: &Request<Incoming>,
+    file: &PageFile,
+    headers: HeaderMap,
+    blobs: &BlobStore,
+) -> Result<Response<PageBody>, Infallible> {
+    // Handle conditional requests for the current ETag and modification time
+    let status = match evaluate(req.method(), req.headers(), &file.hash, file.modified) {
+        Precondition::Proceed => None,
+        Precondition::NotModified => Some(304),
+        Precondition::Failed => Some(412),
+    };
+    if let Some(status) = status {
+        let mut response = Response::builder()
+            .status(status)
+            .body(Either::Left(Full::new(Bytes::new())))
+            .expect("Failed to build HTTP response");
+        response.headers_mut().extend(headers);
         return Ok(response);
     }
 
-    // Return full file
-    let bytes = Bytes::from(file.data.clone());
-    let body = Full::new(bytes);
-    let response = Response::builder()
+    // Answer HEAD requests without reading the contents
+    let head = req.method() == Method::HEAD;
+    let body = if head {
+        Either::Left(Full::new(Bytes::new()))
+    } else {
+        // The body shares the data with the page or the storage cache or streams it from disk
+        match blobs.read(&file.hash, &file.blob).await {
+            Ok(BlobContents::Bytes(data)) => Either::Left(Full::new(data)),
+            Ok(BlobContents::File(body)) => Either::Right(body),
+            Err(err) => {
+                error!(
+                    path = %file.path,
+                    error = format!("{err:#}"),
+                    "Failed to read file contents"
+                );
+                return Ok(text_response(500, "Internal server error").map(Either::Left));
+            }
+        }
+    };
+    let mut builder = Response::builder()
         .status(200)
-        .header("Content-Type", &file.media_type)
-        .header("ETag", &file.hash)
-        .body(body)
-        .expect("Failed to build HTTP response");
+        .header("Content-Type", &file.media_type);
+    if head {
+        builder = builder.header(CONTENT_LENGTH, file.blob.size());
+    }
+    let mut response = builder.body(body).expect("Failed to build HTTP response");
+    response.headers_mut().extend(headers);
     Ok(response)
 }
 
 async fn update_handler(
-    config: Arc<Configuration>,
-    page_prefix: &str,
-    page_lock: &RwLock<Page>,
+    context: Arc<UpdateContext>,
+    page_lock: &Arc<RwLock<Page>>,
+    reason: &'static str,
 ) -> Result<Response<Full<Bytes>>, Infallible> {
-    let start = Instant::now();
-    let result = update_page(page_lock, &config.temp_folder).await;
-    let duration = start.elapsed();
-    if let Err(err) = result {
-        warn!("Update via HTTP handler for {page_prefix} failed after {duration:?}: {err:#}");
-        let bytes = Bytes::from_static(b"Update failed");
-        let body = Full::new(bytes);
-        let response = Response::builder()
-            .status(500)
-            .body(body)
-            .expect("Failed to build HTTP response");
-        Ok(response)
-    } else {
-        info!("Update via HTTP handler for {page_prefix} finished successful after {duration:?}");
-        let bytes = Bytes::from_static(b"Update completed");
-        let body = Full::new(bytes);
-        let response = Response::builder()
-            .status(200)
-            .body(body)
-            .expect("Failed to build HTTP response");
-        Ok(response)
+    let page = page_lock.read().await;
+    let jobs = page.jobs.clone();
+    let prefix = page.config.prefix.clone();
+    let update_secret = page.config.update_secret.clone();
+    drop(page);
+
+    if jobs.is_paused() {
+        metrics().record_webhook(&prefix, "paused");
+        return Ok(text_response(409, "Updates are paused"));
+    }
+    let job = jobs.trigger(page_lock.clone(), context, reason);
+    metrics().record_webhook(&prefix, "accepted");
+    let status_url = update_secret.map(|secret| format!("{prefix}update/{secret}/jobs/{job}"));
+    let body = JobCreatedResponse {
+        job,
+        status_url: status_url.clone(),
+    };
+    let mut response = json_response(202, &body);
+    if let Some(status_url) = status_url
+        && let Ok(value) = HeaderValue::from_str(&status_url)
+    {
+        response.headers_mut().insert(LOCATION, value);
+    }
+    Ok(response)
+}
+
+fn job_status_handler(page: &Page, job: &str) -> Result<Response<Full<Bytes>>, Infallible> {
+    let Ok(job) = job.parse::<u64>() else {
+        return Ok(text_response(404, "Not found"));
+    };
+    let Some(status) = page.jobs.status(job) else {
+        return Ok(text_response(404, "Not found"));
+    };
+    Ok(json_response(200, &JobResponse { job, status }))
+}
+
+async fn webhook_handler(
+    req: Request<Incoming>,
+    context: Arc<UpdateContext>,
+    page_lock: &Arc<RwLock<Page>>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let page = page_lock.read().await;
+    let page_prefix = page.config.prefix.clone();
+    let page_ref = page.config.git_ref.clone();
+    let Some(secret) = page.config.webhook_secret.clone() else {
+        return Ok(text_response(404, "Not found"));
+    };
+    drop(page);
+
+    let (parts, body) = req.into_parts();
+    let body = match Limited::new(body, MAX_WEBHOOK_BYTES).collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            warn!(page = %page_prefix, error = %err, "Failed to read webhook request body");
+            metrics().record_webhook(&page_prefix, "invalid");
+            return Ok(text_response(400, "Invalid request body"));
+        }
+    };
+
+    let forge = match verify_request(&parts.headers, &body, &secret) {
+        Ok(forge) => forge,
+        Err(err) => {
+            warn!(page = %page_prefix, error = format!("{err:#}"), "Rejected webhook request");
+            metrics().record_webhook(&page_prefix, "rejected");
+            return Ok(text_response(401, "Unauthorized"));
+        }
+    };
+    if !is_push_event(&parts.headers, forge) {
+        info!(page = %page_prefix, ?forge, "Ignored non-push webhook event");
+        metrics().record_webhook(&page_prefix, "ignored");
+        return Ok(text_response(200, "Ignored non-push event"));
     }
+    let event = match parse_push_event(&body) {
+        Ok(event) => event,
+        Err(err) => {
+            warn!(
+                page = %page_prefix,
+                ?forge,
+                error = format!("{err:#}"),
+                "Invalid webhook push event"
+            );
+            metrics().record_webhook(&page_prefix, "invalid");
+            return Ok(text_response(400, "Invalid push event"));
+        }
+    };
+    if !event.matches(page_ref.as_deref()) {
+        info!(
+            page = %page_prefix,
+            ?forge,
+            git_ref = %event.git_ref,
+            "Ignored webhook push event for other ref"
+        );
+        metrics().record_webhook(&page_prefix, "ignored");
+        return Ok(text_response(200, "Ignored push to other ref"));
+    }
+
+    info!(
+        page = %page_prefix,
+        ?forge,
+        git_ref = %event.git_ref,
+        "Received webhook push event"
+    );
+    update_handler(context, page_lock, "webhook update").await
+}
+
+/// Permanent redirect to another decoded path of this server, keeping the query string
+fn redirect_response(path: &str, query: Option<&str>) -> Response<Full<Bytes>> {
+    let path = encode_path(path);
+    let location = match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path,
+    };
+    let mut response = text_response(301, "Moved permanently");
+    if let Ok(value) = HeaderValue::from_str(&location) {
+        response.headers_mut().insert(LOCATION, value);
+    }
+    response
+}
+
+pub fn text_response(status: u16, text: &'static str) -> Response<Full<Bytes>> {
+    let bytes = Bytes::from_static(text.as_bytes());
+    let body = Full::new(bytes);
+    Response::builder()
+        .status(status)
+        .body(body)
+        .expect("Failed to build HTTP response")
+}
+
+pub fn json_response(status: u16, value: &impl Serialize) -> Response<Full<Bytes>> {
+    let json = serde_json::to_string(value).expect("Failed to serialize JSON response");
+    let bytes = Bytes::from(json);
+    let body = Full::new(bytes);
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .expect("Failed to build HTTP response")
 }
@@ -0,0 +1,202 @@
+use crate::metrics::metrics;
+use crate::page::{Page, UpdateContext, update_page};
+use jiff::Timestamp;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::spawn;
+use tokio::sync::{RwLock, watch};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Number of finished jobs per page that are kept for status requests
+const MAX_FINISHED_JOBS: usize = 20;
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded {
+        duration_ms: u128,
+        commit: Option<String>,
+    },
+    Failed {
+        duration_ms: u128,
+    },
+}
+
+/// Result of the last finished update of a page
+#[derive(Serialize, Clone)]
+pub struct LastUpdate {
+    pub finished_at: String,
+    #[serde(flatten)]
+    pub status: JobStatus,
+    pub error: Option<String>,
+}
+
+/// Runs the updates of a single page one after another.
+/// Triggers during a running update are coalesced into one follow-up update.
+/// The jobs are kept when a page is reconfigured, so updates of the old and the new
+/// configuration never run at the same time and the job history stays available.
+pub struct UpdateJobs {
+    state: Mutex<JobsState>,
+    finished: watch::Sender<u64>,
+}
+
+#[derive(Default)]
+struct JobsState {
+    last_id: u64,
+    running: Option<u64>,
+    queued: Option<u64>,
+    // Page of the last trigger, which is the reconfigured page after a change
+    queued_page: Option<Arc<RwLock<Page>>>,
+    finished: VecDeque<(u64, JobStatus)>,
+    last_update: Option<LastUpdate>,
+    paused: bool,
+}
+
+impl UpdateJobs {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(JobsState::default()),
+            finished: watch::Sender::new(0),
+        }
+    }
+
+    /// Starts an update or queues a follow-up update if one is already running.
+    /// Returns the ID of the job that will cover this trigger.
+    pub fn trigger(
+        self: &Arc<Self>,
+        page_lock: Arc<RwLock<Page>>,
+        context: Arc<UpdateContext>,
+        reason: &'static str,
+    ) -> u64 {
+        let mut state = self.state.lock().expect("Failed to lock job state");
+        if state.running.is_some() {
+            state.queued_page = Some(page_lock);
+            if let Some(queued) = state.queued {
+                return queued;
+            }
+            state.last_id += 1;
+            state.queued = Some(state.last_id);
+            return state.last_id;
+        }
+        state.last_id += 1;
+        let id = state.last_id;
+        state.running = Some(id);
+        let jobs = self.clone();
+        spawn(async move { jobs.run(id, page_lock, context, reason).await });
+        id
+    }
+
+    /// Waits until the job with the specified ID is finished
+    pub async fn wait(&self, id: u64) {
+        let mut receiver = self.finished.subscribe();
+        _ = receiver.wait_for(|finished| *finished >= id).await;
+    }
+
+    pub fn last_update(&self) -> Option<LastUpdate> {
+        let state = self.state.lock().expect("Failed to lock job state");
+        state.last_update.clone()
+    }
+
+    /// Paused pages are not updated by the background schedule or by webhooks
+    pub fn set_paused(&self, paused: bool) {
+        let mut state = self.state.lock().expect("Failed to lock job state");
+        state.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        let state = self.state.lock().expect("Failed to lock job state");
+        state.paused
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        let state = self.state.lock().expect("Failed to lock job state");
+        if state.running == Some(id) {
+            Some(JobStatus::Running)
+        } else if state.queued == Some(id) {
+            Some(JobStatus::Queued)
+        } else {
+            state
+                .finished
+                .iter()
+                .find(|(finished_id, _)| *finished_id == id)
+                .map(|(_, status)| status.clone())
+        }
+    }
+
+    async fn run(
+        &self,
+        mut id: u64,
+        mut page_lock: Arc<RwLock<Page>>,
+        context: Arc<UpdateContext>,
+        mut reason: &'static str,
+    ) {
+        loop {
+            let start = Instant::now();
+            let result = update_page(&page_lock, &context).await;
+            let duration = start.elapsed();
+            let page = page_lock.read().await;
+            let config = &page.config;
+            metrics().record_update(&config.prefix, result.is_ok(), duration);
+            let (status, error) = if let Err(error) = result {
+                // Errors can contain the repository URL including credentials
+                let error = format!("{error:#}").replace(&config.repo, &config.redacted_repo());
+                warn!(
+                    page = %config.prefix,
+                    repo = %config.redacted_repo(),
+                    git_ref = config.git_ref.as_deref(),
+                    duration_ms = duration.as_millis() as u64,
+                    reason,
+                    error,
+                    "Page update failed"
+                );
+                let status = JobStatus::Failed {
+                    duration_ms: duration.as_millis(),
+                };
+                (status, Some(error))
+            } else {
+                info!(
+                    page = %config.prefix,
+                    repo = %config.redacted_repo(),
+                    git_ref = config.git_ref.as_deref(),
+                    commit = page.last_hash.as_deref(),
+                    duration_ms = duration.as_millis() as u64,
+                    reason,
+                    "Page update finished"
+                );
+                let status = JobStatus::Succeeded {
+                    duration_ms: duration.as_millis(),
+                    commit: page.last_hash.clone(),
+                };
+                (status, None)
+            };
+            drop(page);
+
+            let mut state = self.state.lock().expect("Failed to lock job state");
+            state.last_update = Some(LastUpdate {
+                finished_at: Timestamp::now().to_string(),
+                status: status.clone(),
+                error,
+            });
+            state.finished.push_back((id, status));
+            if state.finished.len() > MAX_FINISHED_JOBS {
+                state.finished.pop_front();
+            }
+            self.finished.send_replace(id);
+            if let Some(queued) = state.queued.take() {
+                state.running = Some(queued);
+                id = queued;
+                if let Some(queued_page) = state.queued_page.take() {
+                    page_lock = queued_page;
+                }
+                reason = "queued update";
+            } else {
+                state.running = None;
+                return;
+            }
+        }
+    }
+}
@@ -1,45 +1,85 @@
 #![forbid(unsafe_code)]
 
+mod access_log;
+mod admin;
+mod blobs;
+mod cache;
+mod cache_control;
+mod check;
+mod conditional;
 mod config;
+mod config_file;
+mod cors;
+mod cron;
+mod export;
+mod files;
 mod git;
+mod health;
 mod http;
+mod jobs;
 mod media_type;
+mod metrics;
 mod page;
 mod pages;
+mod reload;
 mod updates;
+mod url_path;
+mod webhook;
 
-use crate::config::Configuration;
-use crate::http::start_server;
+use crate::access_log::AccessLog;
+use crate::admin::start_admin_server;
+use crate::check::check_pages;
+use crate::config::{Action, Configuration, LogFormat};
+use crate::export::export_page;
+use crate::health::Readiness;
+use crate::http::{bind, start_server};
+use crate::page::UpdateContext;
 use crate::pages::Pages;
+use crate::reload::reload_config;
 use crate::updates::background_updates;
 use anyhow::{Context, Result, bail};
-use clap::Parser;
 use std::sync::Arc;
 use tokio::spawn;
 use tokio::sync::mpsc::channel;
 use tracing::info;
-use tracing_subscriber::FmtSubscriber;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load config
-    let config = Arc::new(Configuration::parse());
+    let (config, config_file) = Configuration::load()?;
+    let config = Arc::new(config);
 
     // Set up logging
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(config.log_level)
+    let filter = if let Some(directives) = &config.log_filter {
+        EnvFilter::try_new(directives).context("Failed to parse log filter")?
+    } else {
+        EnvFilter::default().add_directive(LevelFilter::from_level(config.log_level).into())
+    };
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(filter)
         .with_ansi(false)
-        .with_target(false)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("Failed to set global tracing subscriber");
+        .with_target(false);
+    match config.log_format {
+        LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Json => {
+            tracing::subscriber::set_global_default(builder.json().flatten_event(true).finish())
+        }
+    }
+    .expect("Failed to set global tracing subscriber");
     info!("Starting gitomato version {}...", env!("CARGO_PKG_VERSION"));
 
     // Log configuration values
     config.log();
+    config.validate().context("Invalid configuration")?;
 
     // Set up the different pages
-    let pages = Pages::from_cli_and_env()
+    let file_pages = config_file
+        .as_ref()
+        .map(|file| file.pages.clone())
+        .unwrap_or_default();
+    let pages = Pages::from_config(file_pages)
         .await
         .context("Failed to set up pages")?;
     if pages.is_empty() {
@@ -47,26 +87,93 @@ async fn main() -> Result<()> {
         bail!("Need at least one configured page!");
     }
     pages.log().await;
+    let pages = Arc::new(pages);
+    let readiness = Arc::new(Readiness::new(pages.clone(), &config).await?);
+
+    // Run optional command instead of serving the pages
+    match &config.action {
+        Some(Action::Check { strict }) => {
+            if !check_pages(&config, &pages, *strict).await {
+                bail!("Check found problems");
+            }
+            return Ok(());
+        }
+        Some(Action::Export { page, out }) => {
+            return export_page(&config, &pages, page, out)
+                .await
+                .context(format!("Failed to export page {page}"));
+        }
+        None => {}
+    }
+
+    // Set up everything that can fail before any background task is started
+    let access_log = Arc::new(AccessLog::new(&config).context("Failed to set up access log")?);
+    let listener = bind(config.http_binding, config.http_port)
+        .await
+        .context("Failed to start HTTP server")?;
+    let admin_listener = match config.admin_port {
+        Some(port) => Some(
+            bind(config.admin_binding, port)
+                .await
+                .context("Failed to start admin HTTP server")?,
+        ),
+        None => None,
+    };
+
+    // Restore pages from disk cache
+    let context = Arc::new(UpdateContext::from_config(&config)?);
+    if let Some(cache) = &context.cache {
+        pages.load_cache(cache, &context).await;
+    }
+    // Blob files restored from the disk cache are kept
+    if pages.use_disk_storage().await {
+        context.blobs.clean_folder();
+    }
 
     // Spawn background update task
-    let pages = Arc::new(pages);
     let pages_clone = pages.clone();
     let config_clone = config.clone();
+    let context_clone = context.clone();
     let (stop_sender, stop_receiver) = channel(1);
-    let bg_handle =
-        spawn(async move { background_updates(pages_clone, config_clone, stop_receiver).await });
+    let bg_handle = spawn(async move {
+        background_updates(pages_clone, config_clone, context_clone, stop_receiver).await
+    });
+
+    // Spawn config file reload task
+    let reload_handle =
+        config_file.map(|file| spawn(reload_config(config.clone(), pages.clone(), file)));
+
+    // Start optional admin HTTP server
+    let admin_handle = admin_listener.map(|listener| {
+        info!(
+            "Starting admin HTTP server bound to {}:{}...",
+            config.admin_binding,
+            config.admin_port.unwrap_or_default()
+        );
+        spawn(start_admin_server(
+            listener,
+            config.clone(),
+            pages.clone(),
+            context.clone(),
+            readiness.clone(),
+        ))
+    });
 
     // Start HTTP server
     info!(
         "Starting HTTP server bound to {}:{}...",
         config.http_binding, config.http_port
     );
-    start_server(config, pages)
-        .await
-        .context("Failed to start HTTP server")?;
+    start_server(listener, config, pages, context, readiness, access_log).await;
 
     // Shutdown rest of app after HTTP server stopped
     info!("HTTP server stopped");
+    if let Some(handle) = reload_handle {
+        handle.abort();
+    }
+    if let Some(handle) = admin_handle {
+        handle.await.expect("Failed to join admin HTTP server task");
+    }
     info!("Stopping background task...");
     stop_sender
         .send(())
@@ -94,6 +201,13 @@ fn show_page_setup_help() {
     * PAGE_GIT_SUBFOLDER or --page-git-subfolder (optional)
       Subfolder to check out. Will use the whole repository if not set.
       Example value: "my/sub/folder/"
+    * PAGE_GIT_DEPTH or --page-git-depth (optional)
+      Number of commits to fetch. Last-Modified of each file is the time of the
+      last commit that changed it within these commits, otherwise the time of
+      the checked out commit. Default is 1.
+    * PAGE_NAME or --page-name (optional)
+      Unique name of the page used by the admin API.
+      Default is "page" or "page0", "page1", ... for numbered pages.
     * PAGE_PREFIX --page-prefix (optional)
       Where to mount the page on the HTTP server.
       Default is "/". Other example value: "/mypage/".
@@ -107,20 +221,73 @@ fn show_page_setup_help() {
       When enabled, this will generate a folder listing index with all contained
       files and subfolders for directories without an index page.
       Disabled by default.
+    * PAGE_CLEAN_URLS or --page-clean-urls (optional)
+      Serves HTML files without extension, like /about for about.html
+      or /docs for docs/index.html. Requests are redirected to the canonical URL.
+      Values: off, without-slash (/about) or with-slash (/about/). Default is off.
+    * PAGE_CACHE_CONTROL or --page-cache-control (optional)
+      Cache-Control header rules as pattern=value separated by semicolons.
+      The first matching rule wins. Patterns without slash match the file name.
+      * matches anything, ? a single character, [hash] 8 or more hex digits.
+      Example value: "*.[hash].js=public, max-age=31536000, immutable; *.html=no-cache"
+    * PAGE_CORS_ORIGINS or --page-cors-origins (optional)
+      Enables CORS headers for requests from the listed origins, separated by commas.
+      Use * for any origin or a regular expression with a leading ~,
+      which has to match the whole origin.
+      Example value: "https://example.com, ~https://.*\.example\.com"
+    * PAGE_CORS_METHODS or --page-cors-methods (optional)
+      Methods allowed in CORS preflight requests. Default is "GET, HEAD".
+      A wildcard is not supported, all methods need to be listed.
+    * PAGE_CORS_HEADERS or --page-cors-headers (optional)
+      Request headers allowed in CORS preflight requests, or * for any.
+    * PAGE_CORS_CREDENTIALS or --page-cors-credentials (optional)
+      Allows requests with credentials like cookies. Disabled by default.
+      Can not be combined with the origin *, the origins need to be listed.
+    * PAGE_CORS_MAX_AGE or --page-cors-max-age (optional)
+      Seconds browsers can cache the result of a CORS preflight request.
+    * PAGE_STORAGE or --page-storage (optional)
+      Keeps the files in memory or writes them to the storage folder and reads
+      them on demand for large pages. Values: memory or disk. Default is memory.
     * PAGE_UPDATE_SECRET or --page-update-secret (optional)
       When set, this activates a HTTP GET webhook endpoint for automatic updates.
-      When called, this will trigger a git update for this page.
+      When called, this will trigger a git update for this page in the background.
+      The status of the update job can be checked at the returned status URL.
       Example value: "my-SUPER-secr3t"
       Resulting endpoint: GET http://server.com/page-prefix/update/my-SUPER-secr3t
+    * PAGE_WEBHOOK_SECRET or --page-webhook-secret (optional)
+      When set, this activates a signed HTTP POST webhook endpoint for updates.
+      Supports GitHub, GitLab, Gitea and Forgejo push events in JSON format.
+      Only pushes to the ref of this page will trigger an update.
+      Resulting endpoint: POST http://server.com/page-prefix/update
     * PAGE_MAX_BYTES or --page-max-bytes (optional)
       Since all data is kept in memory you can configure a max size in bytes.
       If the checkout of this page is over this limit, the page update will fail.
       By default, there is no limit set.
+    * PAGE_MAX_FILE_BYTES or --page-max-file-bytes (optional)
+      Max size in bytes of a single file. No limit by default.
+    * PAGE_MAX_FILES or --page-max-files (optional)
+      Max number of files of the page. No limit by default.
+    * PAGE_MAX_PATH_DEPTH or --page-max-path-depth (optional)
+      Max number of path segments of a file, like 2 for docs/index.html.
+      No limit by default.
+    * PAGE_MAX_FILE_BYTES_POLICY, PAGE_MAX_FILES_POLICY, PAGE_MAX_PATH_DEPTH_POLICY
+      or --page-max-file-bytes-policy, ... (optional)
+      What happens with files over these limits: fail lets the page update fail,
+      skip leaves out the file with a warning. Default is fail.
+    * PAGE_INTERVAL or --page-interval (optional)
+      Background update interval in seconds for this page.
+      Overrides the global update interval.
+    * PAGE_CRON or --page-cron (optional)
+      Cron schedule with five fields for background updates, evaluated in UTC.
+      Cannot be combined with an interval. Example value: "0 3 * * *"
+    * PAGE_WEBHOOK_ONLY or --page-webhook-only (optional)
+      When enabled, the page is only checked out once at startup and then
+      only updated using the webhook. Disabled by default.
 
     Simplest possible setup for a single page using command line arguments:
     gitomato --page-git-repo=https://github.com/user/repo.git
 
-    When you want multiple pages, you need to configure numbered pages starting from 0:
+    When you want multiple pages, you need to configure numbered pages:
     gitomato --page0-git-repo=https://github.com/user/repo0.git \
              --page1-git-repo=https://github.com/user/repo1.git \
              ...
@@ -131,6 +298,12 @@ fn show_page_setup_help() {
     If you have command line arguments and environment variables for the same page,
     the command line arguments will win and the environment variables will be ignored.
 
+    Unknown page arguments and invalid values are rejected with an error.
+    Boolean values can be true/false, on/off or enabled/disabled.
+
+    Alternatively, all pages and global parameters can be configured
+    in a TOML or YAML file using --config-file. See README for details.
+
     Run gitomato --help for a reference of all global configuration parameters.
     "#
     );
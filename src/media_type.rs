@@ -1,4 +1,4 @@
-const DEFAULT_TYPE: &str = "application/octet-stream";
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
 
 pub fn media_type_from_path(_file_path: &str) -> &'static str {
     if let Some((_, ext)) = _file_path.rsplit_once(".") {
@@ -0,0 +1,355 @@
+use crate::blobs::BlobStore;
+use crate::pages::Pages;
+use http_body_util::Full;
+use hyper::Response;
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Label value used for requests that do not belong to any page
+pub const NO_PAGE: &str = "none";
+
+const REQUEST_BUCKETS: &[f64] = &[0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+const UPDATE_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Global metrics collected by the application
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    data: Mutex<MetricsData>,
+}
+
+#[derive(Default)]
+struct MetricsData {
+    requests: BTreeMap<(String, u16), u64>,
+    request_durations: BTreeMap<String, Histogram>,
+    response_bytes: BTreeMap<String, u64>,
+    updates: BTreeMap<(String, &'static str), u64>,
+    update_durations: BTreeMap<String, Histogram>,
+    last_success: BTreeMap<String, f64>,
+    webhooks: BTreeMap<(String, &'static str), u64>,
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, page: &str) {
+        let page = escape(page);
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            _ = writeln!(
+                out,
+                "{name}_bucket{{page=\"{page}\",le=\"{bound}\"}} {count}"
+            );
+        }
+        let count = self.count;
+        _ = writeln!(out, "{name}_bucket{{page=\"{page}\",le=\"+Inf\"}} {count}");
+        _ = writeln!(out, "{name}_sum{{page=\"{page}\"}} {}", self.sum);
+        _ = writeln!(out, "{name}_count{{page=\"{page}\"}} {count}");
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self, page: &str, status: u16, bytes: u64, duration: Duration) {
+        let mut data = self.data.lock().expect("Failed to lock metrics");
+        *data.requests.entry((page.to_string(), status)).or_default() += 1;
+        *data.response_bytes.entry(page.to_string()).or_default() += bytes;
+        data.request_durations
+            .entry(page.to_string())
+            .or_insert_with(|| Histogram::new(REQUEST_BUCKETS))
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_update(&self, page: &str, success: bool, duration: Duration) {
+        let mut data = self.data.lock().expect("Failed to lock metrics");
+        let result = if success { "success" } else { "failure" };
+        *data.updates.entry((page.to_string(), result)).or_default() += 1;
+        data.update_durations
+            .entry(page.to_string())
+            .or_insert_with(|| Histogram::new(UPDATE_BUCKETS))
+            .observe(duration.as_secs_f64());
+        if success {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            data.last_success.insert(page.to_string(), now);
+        }
+    }
+
+    /// Counts webhook invocations with a short result like "accepted" or "rejected"
+    pub fn record_webhook(&self, page: &str, result: &'static str) {
+        let mut data = self.data.lock().expect("Failed to lock metrics");
+        *data.webhooks.entry((page.to_string(), result)).or_default() += 1;
+    }
+
+    /// Removes all metrics of a page that no longer exists
+    pub fn remove_page(&self, page: &str) {
+        let mut data = self.data.lock().expect("Failed to lock metrics");
+        data.requests.retain(|(p, _), _| p != page);
+        data.request_durations.remove(page);
+        data.response_bytes.remove(page);
+        data.updates.retain(|(p, _), _| p != page);
+        data.update_durations.remove(page);
+        data.last_success.remove(page);
+        data.webhooks.retain(|(p, _), _| p != page);
+    }
+
+    /// Renders all metrics in the Prometheus text format
+    pub async fn render(&self, pages: &Pages, blobs: &BlobStore) -> String {
+        let mut out = String::new();
+
+        // Page metrics are collected directly from the current page state
+        header(
+            &mut out,
+            "gitomato_page_info",
+            "gauge",
+            "Current commit of each page",
+        );
+        let mut files = String::new();
+        let mut bytes = String::new();
+        for page_lock in pages.snapshot().iter() {
+            let page = page_lock.read().await;
+            let prefix = escape(&page.config.prefix);
+            let commit = escape(page.last_hash.as_deref().unwrap_or_default());
+            let size: usize = page.files.iter().map(|f| f.blob.size()).sum();
+            _ = writeln!(
+                out,
+                "gitomato_page_info{{page=\"{prefix}\",commit=\"{commit}\"}} 1"
+            );
+            _ = writeln!(
+                files,
+                "gitomato_page_files{{page=\"{prefix}\"}} {}",
+                page.files.len()
+            );
+            _ = writeln!(bytes, "gitomato_page_bytes{{page=\"{prefix}\"}} {size}");
+        }
+        header(
+            &mut out,
+            "gitomato_page_files",
+            "gauge",
+            "Number of files per page",
+        );
+        out.push_str(&files);
+        header(
+            &mut out,
+            "gitomato_page_bytes",
+            "gauge",
+            "Size of all files per page in bytes",
+        );
+        out.push_str(&bytes);
+
+        // Files shared by multiple pages are only counted once
+        let usage = blobs.usage();
+        header(
+            &mut out,
+            "gitomato_blobs",
+            "gauge",
+            "Number of distinct file contents of all pages",
+        );
+        _ = writeln!(
+            out,
+            "gitomato_blobs{{storage=\"memory\"}} {}",
+            usage.memory_blobs
+        );
+        _ = writeln!(
+            out,
+            "gitomato_blobs{{storage=\"disk\"}} {}",
+            usage.disk_blobs
+        );
+        header(
+            &mut out,
+            "gitomato_blob_bytes",
+            "gauge",
+            "Size of all distinct file contents of all pages in bytes",
+        );
+        _ = writeln!(
+            out,
+            "gitomato_blob_bytes{{storage=\"memory\"}} {}",
+            usage.memory_bytes
+        );
+        _ = writeln!(
+            out,
+            "gitomato_blob_bytes{{storage=\"disk\"}} {}",
+            usage.disk_bytes
+        );
+        header(
+            &mut out,
+            "gitomato_storage_cache_bytes",
+            "gauge",
+            "Size of the cached file contents of pages with disk storage in bytes",
+        );
+        _ = writeln!(out, "gitomato_storage_cache_bytes {}", usage.cache_bytes);
+
+        let data = self.data.lock().expect("Failed to lock metrics");
+
+        header(
+            &mut out,
+            "gitomato_http_requests_total",
+            "counter",
+            "Number of HTTP requests by page and status",
+        );
+        for ((page, status), count) in &data.requests {
+            let page = escape(page);
+            _ = writeln!(
+                out,
+                "gitomato_http_requests_total{{page=\"{page}\",status=\"{status}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "gitomato_http_request_duration_seconds",
+            "histogram",
+            "Duration of HTTP requests by page",
+        );
+        for (page, histogram) in &data.request_durations {
+            histogram.render(&mut out, "gitomato_http_request_duration_seconds", page);
+        }
+
+        header(
+            &mut out,
+            "gitomato_http_response_bytes_total",
+            "counter",
+            "Number of response body bytes served by page",
+        );
+        for (page, bytes) in &data.response_bytes {
+            let page = escape(page);
+            _ = writeln!(
+                out,
+                "gitomato_http_response_bytes_total{{page=\"{page}\"}} {bytes}"
+            );
+        }
+
+        header(
+            &mut out,
+            "gitomato_http_not_modified_ratio",
+            "gauge",
+            "Ratio of 304 responses to all successful file responses by page",
+        );
+        for page in data.response_bytes.keys() {
+            let count = |status| {
+                let key = (page.clone(), status);
+                data.requests.get(&key).copied().unwrap_or_default()
+            };
+            let not_modified = count(304);
+            let total = not_modified + count(200);
+            if total > 0 {
+                let ratio = not_modified as f64 / total as f64;
+                let page = escape(page);
+                _ = writeln!(
+                    out,
+                    "gitomato_http_not_modified_ratio{{page=\"{page}\"}} {ratio}"
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "gitomato_page_updates_total",
+            "counter",
+            "Number of page updates by result",
+        );
+        for ((page, result), count) in &data.updates {
+            let page = escape(page);
+            _ = writeln!(
+                out,
+                "gitomato_page_updates_total{{page=\"{page}\",result=\"{result}\"}} {count}"
+            );
+        }
+
+        header(
+            &mut out,
+            "gitomato_page_update_duration_seconds",
+            "histogram",
+            "Duration of page updates",
+        );
+        for (page, histogram) in &data.update_durations {
+            histogram.render(&mut out, "gitomato_page_update_duration_seconds", page);
+        }
+
+        header(
+            &mut out,
+            "gitomato_page_last_success_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last successful page update",
+        );
+        for (page, timestamp) in &data.last_success {
+            let page = escape(page);
+            _ = writeln!(
+                out,
+                "gitomato_page_last_success_timestamp_seconds{{page=\"{page}\"}} {timestamp}"
+            );
+        }
+
+        header(
+            &mut out,
+            "gitomato_webhook_requests_total",
+            "counter",
+            "Number of webhook invocations by page and result",
+        );
+        for ((page, result), count) in &data.webhooks {
+            let page = escape(page);
+            _ = writeln!(
+                out,
+                "gitomato_webhook_requests_total{{page=\"{page}\",result=\"{result}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    _ = writeln!(out, "# HELP {name} {help}");
+    _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace("\\", "\\\\")
+        .replace("\"", "\\\"")
+        .replace("\n", "\\n")
+}
+
+/// Handles the Prometheus metrics endpoint
+pub async fn metrics_handler(pages: &Pages, blobs: &BlobStore) -> Response<Full<Bytes>> {
+    let text = metrics().render(pages, blobs).await;
+    let body = Full::new(Bytes::from(text));
+    Response::builder()
+        .status(200)
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body)
+        .expect("Failed to build HTTP response")
+}
@@ -1,194 +1,607 @@
-use crate::git::get_git_files;
+use crate::blobs::{Blob, BlobStore, BlobTarget};
+use crate::cache::DiskCache;
+use crate::cache_control::CacheControlRules;
+use crate::config::Configuration;
+use crate::cors::Cors;
+use crate::cron::CronSchedule;
+use crate::files::PageFiles;
+use crate::git::{FileLimits, LimitPolicy, SkippedFile, get_git_files};
+use crate::jobs::UpdateJobs;
 use crate::media_type::media_type_from_path;
-use anyhow::{Context, Result, bail};
-use std::collections::HashMap;
-use std::path::Path;
+use crate::url_path::{encode_path, escape_html};
+use anyhow::{Context, Result, bail, ensure};
+use jiff::Timestamp;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tokio::task::spawn_blocking;
+use tracing::warn;
 
 pub struct Page {
-    // Settings
+    pub config: PageConfig,
+
+    // State
+    pub last_hash: Option<String>,
+    pub files: PageFiles,
+    pub jobs: Arc<UpdateJobs>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct PageConfig {
+    pub name: String,
     pub repo: String,
     pub git_ref: Option<String>,
     pub subfolder: Option<String>,
+    pub git_depth: NonZero<u32>,
     pub max_bytes: Option<u64>,
+    pub max_file_bytes: Option<u64>,
+    pub max_file_bytes_policy: LimitPolicy,
+    pub max_files: Option<usize>,
+    pub max_files_policy: LimitPolicy,
+    pub max_path_depth: Option<usize>,
+    pub max_path_depth_policy: LimitPolicy,
     pub prefix: String,
     pub auto_index: bool,
     pub auto_list: bool,
+    pub clean_urls: CleanUrls,
+    pub cache_control: CacheControlRules,
+    pub cors: Option<Cors>,
+    pub storage: PageStorage,
     pub update_secret: Option<String>,
-
-    // State
-    pub last_hash: Option<String>,
-    pub files: Vec<PageFile>,
+    pub webhook_secret: Option<String>,
+    pub interval: Option<u64>,
+    pub cron: Option<CronSchedule>,
+    pub webhook_only: bool,
 }
 
+#[derive(Clone)]
 pub struct PageFile {
     pub path: String,
     pub media_type: String,
     pub hash: String,
-    pub data: Vec<u8>,
+    /// Contents shared with all other files of the same git object
+    pub blob: Arc<Blob>,
+    /// Time of the last commit that changed the file
+    pub modified: Option<Timestamp>,
 }
 
-impl Page {
-    pub fn from_env(number: Option<usize>) -> Option<Self> {
-        let page = if let Some(number) = number {
-            format!("PAGE{number}")
+/// Unparsed settings of a page by their upper case names
+pub type PageSettings = HashMap<String, String>;
+
+/// Names of all page settings as used in the environment variables after the page prefix
+pub const PAGE_KEYS: &[&str] = &[
+    "NAME",
+    "GIT_REPO",
+    "GIT_REF",
+    "GIT_SUBFOLDER",
+    "GIT_DEPTH",
+    "MAX_BYTES",
+    "MAX_FILE_BYTES",
+    "MAX_FILE_BYTES_POLICY",
+    "MAX_FILES",
+    "MAX_FILES_POLICY",
+    "MAX_PATH_DEPTH",
+    "MAX_PATH_DEPTH_POLICY",
+    "PREFIX",
+    "AUTO_INDEX",
+    "AUTO_LIST",
+    "CLEAN_URLS",
+    "CACHE_CONTROL",
+    "CORS_ORIGINS",
+    "CORS_METHODS",
+    "CORS_HEADERS",
+    "CORS_CREDENTIALS",
+    "CORS_MAX_AGE",
+    "STORAGE",
+    "UPDATE_SECRET",
+    "WEBHOOK_SECRET",
+    "INTERVAL",
+    "CRON",
+    "WEBHOOK_ONLY",
+];
+
+/// File names served for folder paths when auto index is enabled, in this order
+pub const INDEX_FILES: &[&str] = &[
+    "index.html",
+    "index.htm",
+    "default.html",
+    "default.htm",
+    "home.html",
+    "home.htm",
+];
+
+/// Serves HTML files without their extension, like `/about` for `about.html`
+/// or `/docs` for `docs/index.html`. Both modes redirect to the canonical form,
+/// either without or with trailing slash, and redirect requests for HTML files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CleanUrls {
+    Off,
+    WithoutSlash,
+    WithSlash,
+}
+
+impl FromStr for CleanUrls {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "off" | "false" | "disabled" => Ok(Self::Off),
+            "without-slash" => Ok(Self::WithoutSlash),
+            "with-slash" => Ok(Self::WithSlash),
+            _ => bail!("Invalid value '{value}', expected off, without-slash or with-slash"),
+        }
+    }
+}
+
+impl Display for CleanUrls {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Off => "off",
+            Self::WithoutSlash => "without-slash",
+            Self::WithSlash => "with-slash",
+        };
+        f.write_str(value)
+    }
+}
+
+/// Where the file contents of a page are kept while serving.
+/// Disk storage writes them to the storage folder and reads them on demand.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PageStorage {
+    Memory,
+    Disk,
+}
+
+impl FromStr for PageStorage {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "disk" => Ok(Self::Disk),
+            _ => bail!("Invalid value '{value}', expected memory or disk"),
+        }
+    }
+}
+
+impl Display for PageStorage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Self::Memory => "memory",
+            Self::Disk => "disk",
+        };
+        f.write_str(value)
+    }
+}
+
+/// Result of looking up a request path in a page
+pub enum Lookup<'a> {
+    File(&'a PageFile),
+    Redirect(String),
+    NotFound,
+}
+
+/// Resolved background update schedule of a page
+pub enum UpdateSchedule {
+    Interval(Duration),
+    Cron(CronSchedule),
+    WebhookOnly,
+}
+
+impl PageConfig {
+    /// Reads the settings of a page from environment variables like `PAGE3_GIT_REPO`
+    /// and command line arguments like `--page3-git-repo`.
+    /// Command line arguments override environment variables.
+    pub fn env_settings(number: Option<usize>) -> PageSettings {
+        let (env_page, cli_page) = if let Some(number) = number {
+            (format!("PAGE{number}"), format!("page{number}"))
         } else {
-            String::from("PAGE")
+            (String::from("PAGE"), String::from("page"))
         };
-        let repo = std::env::var(format!("{page}_GIT_REPO")).ok()?;
-        let git_ref = std::env::var(format!("{page}_GIT_REF")).ok();
-        let subfolder = std::env::var(format!("{page}_GIT_SUBFOLDER")).ok();
-        let max_bytes = std::env::var(format!("{page}_MAX_BYTES"))
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok());
-        let prefix = std::env::var(format!("{page}_PREFIX")).unwrap_or(String::from("/"));
-        let auto_index = std::env::var(format!("{page}_AUTO_INDEX"))
-            .ok()
-            .map(|s| s.to_lowercase())
-            .map(|s| s == "true" || s == "on" || s == "enabled")
+        let mut settings = PageSettings::new();
+        for key in PAGE_KEYS {
+            let arg = format!("{cli_page}-{}", key.to_lowercase().replace("_", "-"));
+            let value =
+                get_cli_arg(arg).or_else(|| std::env::var(format!("{env_page}_{key}")).ok());
+            if let Some(value) = value {
+                settings.insert(key.to_string(), value);
+            }
+        }
+        settings
+    }
+
+    /// Reads the settings of a page from a JSON object with lower case setting names
+    /// like `{"git_repo": "https://...", "interval": 60}`.
+    /// Unknown settings are rejected, the name is always taken from the argument.
+    pub fn from_json(name: &str, json: &serde_json::Value) -> Result<Self> {
+        let mut settings = Self::json_settings(json)?;
+        settings.insert(String::from("NAME"), name.to_string());
+        Self::from_settings(&settings)
+    }
+
+    /// Reads the settings of a page from a JSON object without checking their values.
+    /// Unknown settings and the name are rejected.
+    pub fn json_settings(json: &serde_json::Value) -> Result<PageSettings> {
+        let object = json
+            .as_object()
+            .context("Page configuration must be a JSON object")?;
+        let mut settings = PageSettings::new();
+        for (key, value) in object {
+            let upper_key = key.to_uppercase();
+            ensure!(
+                PAGE_KEYS.contains(&upper_key.as_str()) && upper_key != "NAME",
+                "Unknown page setting '{key}'"
+            );
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => bail!("Invalid value for page setting '{key}'"),
+            };
+            settings.insert(upper_key, value);
+        }
+        Ok(settings)
+    }
+
+    /// Parses all page settings, the name and the git repo are required
+    pub fn from_settings(settings: &PageSettings) -> Result<Self> {
+        let lookup = |key: &str| settings.get(key).cloned();
+        let repo = lookup("GIT_REPO").context("Page configuration needs a git repo")?;
+        let name = lookup("NAME").context("Page configuration needs a name")?;
+        ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+            "Page name '{name}' must only contain letters, digits, dashes and underscores"
+        );
+        let git_ref = lookup("GIT_REF");
+        let subfolder = lookup("GIT_SUBFOLDER");
+        let git_depth = lookup("GIT_DEPTH")
+            .map(|s| s.parse::<NonZero<u32>>())
+            .transpose()
+            .context("Failed to parse git depth, expected a positive number of commits")?
+            .unwrap_or(NonZero::<u32>::MIN);
+        let max_bytes = lookup("MAX_BYTES")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("Failed to parse max bytes, expected a number of bytes")?;
+        let max_file_bytes = lookup("MAX_FILE_BYTES")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("Failed to parse max file bytes, expected a number of bytes")?;
+        let max_file_bytes_policy = lookup("MAX_FILE_BYTES_POLICY")
+            .map(|s| s.parse::<LimitPolicy>())
+            .transpose()
+            .context("Failed to parse max file bytes policy")?
+            .unwrap_or_default();
+        let max_files = lookup("MAX_FILES")
+            .map(|s| s.parse::<usize>())
+            .transpose()
+            .context("Failed to parse max files, expected a number of files")?;
+        let max_files_policy = lookup("MAX_FILES_POLICY")
+            .map(|s| s.parse::<LimitPolicy>())
+            .transpose()
+            .context("Failed to parse max files policy")?
+            .unwrap_or_default();
+        let max_path_depth = lookup("MAX_PATH_DEPTH")
+            .map(|s| s.parse::<usize>())
+            .transpose()
+            .context("Failed to parse max path depth, expected a number of path segments")?;
+        let max_path_depth_policy = lookup("MAX_PATH_DEPTH_POLICY")
+            .map(|s| s.parse::<LimitPolicy>())
+            .transpose()
+            .context("Failed to parse max path depth policy")?
+            .unwrap_or_default();
+        let prefix = lookup("PREFIX").unwrap_or(String::from("/"));
+        let auto_index = lookup("AUTO_INDEX")
+            .map(|s| parse_bool(&s))
+            .transpose()
+            .context("Failed to parse auto index")?
             .unwrap_or(true);
-        let auto_list = std::env::var(format!("{page}_AUTO_LIST"))
-            .ok()
-            .map(|s| s.to_lowercase())
-            .map(|s| s == "true" || s == "on" || s == "enabled")
+        let auto_list = lookup("AUTO_LIST")
+            .map(|s| parse_bool(&s))
+            .transpose()
+            .context("Failed to parse auto list")?
+            .unwrap_or(false);
+        let clean_urls = lookup("CLEAN_URLS")
+            .map(|s| s.parse::<CleanUrls>())
+            .transpose()
+            .context("Failed to parse clean URLs")?
+            .unwrap_or(CleanUrls::Off);
+        let cache_control = lookup("CACHE_CONTROL")
+            .map(|s| s.parse::<CacheControlRules>())
+            .transpose()
+            .context("Failed to parse cache control rules")?
+            .unwrap_or_default();
+        let cors_credentials = lookup("CORS_CREDENTIALS")
+            .map(|s| parse_bool(&s))
+            .transpose()
+            .context("Failed to parse CORS credentials")?
             .unwrap_or(false);
-        let update_secret = std::env::var(format!("{page}_UPDATE_SECRET")).ok();
-        Some(Self {
+        let cors_max_age = lookup("CORS_MAX_AGE")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("Failed to parse CORS max age, expected a number of seconds")?;
+        let cors = if let Some(origins) = lookup("CORS_ORIGINS") {
+            let methods = lookup("CORS_METHODS");
+            let headers = lookup("CORS_HEADERS");
+            let cors = Cors::new(
+                &origins,
+                methods.as_deref(),
+                headers.as_deref(),
+                cors_credentials,
+                cors_max_age,
+            )
+            .context("Failed to parse CORS settings")?;
+            Some(cors)
+        } else {
+            let other = [
+                "CORS_METHODS",
+                "CORS_HEADERS",
+                "CORS_CREDENTIALS",
+                "CORS_MAX_AGE",
+            ];
+            ensure!(
+                other.iter().all(|key| lookup(key).is_none()),
+                "CORS settings need allowed origins"
+            );
+            None
+        };
+        let storage = lookup("STORAGE")
+            .map(|s| s.parse::<PageStorage>())
+            .transpose()
+            .context("Failed to parse storage")?
+            .unwrap_or(PageStorage::Memory);
+        let update_secret = lookup("UPDATE_SECRET");
+        let webhook_secret = lookup("WEBHOOK_SECRET");
+        let interval = lookup("INTERVAL")
+            .map(|s| s.parse::<u64>())
+            .transpose()
+            .context("Failed to parse update interval")?;
+        let cron = lookup("CRON")
+            .map(|s| s.parse::<CronSchedule>())
+            .transpose()
+            .context("Failed to parse cron schedule")?;
+        let webhook_only = lookup("WEBHOOK_ONLY")
+            .map(|s| parse_bool(&s))
+            .transpose()
+            .context("Failed to parse webhook only")?
+            .unwrap_or(false);
+        ensure!(
+            interval.is_none() || cron.is_none(),
+            "Update interval and cron schedule cannot be combined"
+        );
+        ensure!(
+            !webhook_only || (interval.is_none() && cron.is_none()),
+            "Webhook only mode cannot be combined with an update interval or cron schedule"
+        );
+        Ok(Self {
+            name,
             repo,
             git_ref,
             subfolder,
+            git_depth,
             max_bytes,
+            max_file_bytes,
+            max_file_bytes_policy,
+            max_files,
+            max_files_policy,
+            max_path_depth,
+            max_path_depth_policy,
             prefix,
             auto_index,
             auto_list,
+            clean_urls,
+            cache_control,
+            cors,
+            storage,
             update_secret,
-            last_hash: None,
-            files: Vec::new(),
+            webhook_secret,
+            interval,
+            cron,
+            webhook_only,
         })
     }
 
-    pub fn from_cli(number: Option<usize>) -> Option<Self> {
-        let page = if let Some(number) = number {
-            format!("page{number}")
-        } else {
-            String::from("page")
+    /// Names of all settings that differ between both configurations
+    pub fn differences(&self, other: &PageConfig) -> Vec<&'static str> {
+        let checks = [
+            ("name", self.name != other.name),
+            ("git_repo", self.repo != other.repo),
+            ("git_ref", self.git_ref != other.git_ref),
+            ("git_subfolder", self.subfolder != other.subfolder),
+            ("git_depth", self.git_depth != other.git_depth),
+            ("max_bytes", self.max_bytes != other.max_bytes),
+            (
+                "max_file_bytes",
+                self.max_file_bytes != other.max_file_bytes,
+            ),
+            (
+                "max_file_bytes_policy",
+                self.max_file_bytes_policy != other.max_file_bytes_policy,
+            ),
+            ("max_files", self.max_files != other.max_files),
+            (
+                "max_files_policy",
+                self.max_files_policy != other.max_files_policy,
+            ),
+            (
+                "max_path_depth",
+                self.max_path_depth != other.max_path_depth,
+            ),
+            (
+                "max_path_depth_policy",
+                self.max_path_depth_policy != other.max_path_depth_policy,
+            ),
+            ("prefix", self.prefix != other.prefix),
+            ("auto_index", self.auto_index != other.auto_index),
+            ("auto_list", self.auto_list != other.auto_list),
+            ("clean_urls", self.clean_urls != other.clean_urls),
+            ("cache_control", self.cache_control != other.cache_control),
+            ("cors", self.cors != other.cors),
+            ("storage", self.storage != other.storage),
+            ("update_secret", self.update_secret != other.update_secret),
+            (
+                "webhook_secret",
+                self.webhook_secret != other.webhook_secret,
+            ),
+            ("interval", self.interval != other.interval),
+            ("cron", self.cron != other.cron),
+            ("webhook_only", self.webhook_only != other.webhook_only),
+        ];
+        checks
+            .into_iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// True if both configurations result in the same page files
+    pub fn same_content(&self, other: &PageConfig) -> bool {
+        self.repo == other.repo
+            && self.git_ref == other.git_ref
+            && self.subfolder == other.subfolder
+            && self.git_depth == other.git_depth
+            && self.prefix == other.prefix
+            && self.storage == other.storage
+            && self.file_limits() == other.file_limits()
+    }
+
+    pub fn file_limits(&self) -> FileLimits {
+        FileLimits {
+            max_bytes: self.max_bytes,
+            max_file_bytes: self.max_file_bytes,
+            max_file_bytes_policy: self.max_file_bytes_policy,
+            max_files: self.max_files,
+            max_files_policy: self.max_files_policy,
+            max_path_depth: self.max_path_depth,
+            max_path_depth_policy: self.max_path_depth_policy,
+        }
+    }
+
+    /// Repository URL with any credentials replaced by a placeholder
+    pub fn redacted_repo(&self) -> String {
+        let Some((scheme, rest)) = self.repo.split_once("://") else {
+            return self.repo.clone();
         };
-        let repo = get_cli_arg(format!("{page}-git-repo"))?;
-        let git_ref = get_cli_arg(format!("{page}-git-ref"));
-        let subfolder = get_cli_arg(format!("{page}-git-subfolder"));
-        let max_bytes =
-            get_cli_arg(format!("{page}-max-bytes")).and_then(|s| s.parse::<u64>().ok());
-        let prefix = get_cli_arg(format!("{page}-prefix")).unwrap_or(String::from("/"));
-        let auto_index = get_cli_arg(format!("{page}-auto-index"))
-            .map(|s| s.to_lowercase())
-            .map(|s| s == "true" || s == "on" || s == "enabled")
-            .unwrap_or(true);
-        let auto_list = get_cli_arg(format!("{page}-auto-list"))
-            .map(|s| s.to_lowercase())
-            .map(|s| s == "true" || s == "on" || s == "enabled")
-            .unwrap_or(false);
-        let update_secret = get_cli_arg(format!("{page}-update-secret"));
-        Some(Self {
-            repo,
-            git_ref,
-            subfolder,
-            max_bytes,
-            prefix,
-            auto_index,
-            auto_list,
-            update_secret,
+        let authority_end = rest.find('/').unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+        if let Some((_, host)) = authority.rsplit_once('@') {
+            format!("{scheme}://***@{host}{path}")
+        } else {
+            self.repo.clone()
+        }
+    }
+
+    pub fn schedule(&self, default_interval: u64) -> UpdateSchedule {
+        if self.webhook_only {
+            UpdateSchedule::WebhookOnly
+        } else if let Some(cron) = &self.cron {
+            UpdateSchedule::Cron(cron.clone())
+        } else {
+            let secs = self.interval.unwrap_or(default_interval);
+            UpdateSchedule::Interval(Duration::from_secs(secs))
+        }
+    }
+}
+
+impl Page {
+    pub fn new(config: PageConfig) -> Self {
+        Self {
+            config,
             last_hash: None,
-            files: Vec::new(),
-        })
+            files: PageFiles::default(),
+            jobs: Arc::new(UpdateJobs::new()),
+        }
     }
 
     pub fn find_file(&self, path: &str) -> Option<&PageFile> {
         // Check for any index files
-        if self.auto_index && path.ends_with("/") {
-            let names = [
-                "index.html",
-                "index.htm",
-                "default.html",
-                "default.htm",
-                "home.html",
-                "home.htm",
-            ];
-            for name in names {
+        if self.config.auto_index && path.ends_with("/") {
+            for name in INDEX_FILES {
                 let index_path = format!("{path}{name}");
-                if let Some(file) = self.files.iter().find(|&f| index_path == f.path) {
+                if let Some(file) = self.files.get(&index_path) {
                     return Some(file);
                 }
             }
         }
 
         // Normal direct search
-        self.files.iter().find(|&f| path == f.path)
+        self.files.get(path)
     }
 
-    pub fn list_folder(&self, path: &str) -> Option<String> {
-        // Helper struct for listed entries
-        struct ListEntry {
-            size: Option<usize>,
-            hash: Option<String>,
-        }
+    /// Finds the file for a request path including clean URLs and canonical redirects
+    pub fn lookup(&self, path: &str) -> Lookup<'_> {
+        let mode = self.config.clean_urls;
+        let prefix = &self.config.prefix;
+        if mode != CleanUrls::Off {
+            // Redirect HTML files to their clean URL
+            if self.files.get(path).is_some()
+                && let Some(base) = path.strip_suffix(".html")
+            {
+                let base = base.strip_suffix("/index").unwrap_or(base);
+                return Lookup::Redirect(clean_url(base, prefix, mode));
+            }
 
-        // Collect files and folders of current path
-        let mut entries = HashMap::new();
-        for file in &self.files {
-            if let Some(stripped) = file.path.strip_prefix(path) {
-                if let Some((child, _)) = stripped.split_once("/") {
-                    // Folder
-                    let link = format!("{child}/");
-                    entries.insert(
-                        link,
-                        ListEntry {
-                            size: None,
-                            hash: None,
-                        },
-                    );
+            // Resolve clean URLs to HTML files
+            let base = path.strip_suffix('/').unwrap_or(path);
+            let target = if path == prefix {
+                self.files.get(&format!("{prefix}index.html"))
+            } else {
+                self.files
+                    .get(&format!("{base}.html"))
+                    .or_else(|| self.files.get(&format!("{base}/index.html")))
+            };
+            if let Some(file) = target {
+                let canonical = clean_url(base, prefix, mode);
+                return if path == canonical {
+                    Lookup::File(file)
                 } else {
-                    // Normal file
-                    let link = stripped.to_string();
-                    entries.insert(
-                        link,
-                        ListEntry {
-                            size: Some(file.data.len()),
-                            hash: Some(file.hash.clone()),
-                        },
-                    );
-                }
+                    Lookup::Redirect(canonical)
+                };
             }
         }
 
-        // Early out in case there are no files or folders
-        if entries.is_empty() {
-            return None;
+        if let Some(file) = self.find_file(path) {
+            return Lookup::File(file);
         }
 
+        // Redirect folders to their path with trailing slash,
+        // but only if there is an index file or a folder listing to be served there
+        if !path.ends_with('/') {
+            let folder = format!("{path}/");
+            let servable = self.config.auto_list || self.find_file(&folder).is_some();
+            if servable && self.files.is_folder(&folder) {
+                return Lookup::Redirect(folder);
+            }
+        }
+        Lookup::NotFound
+    }
+
+    pub fn list_folder(&self, path: &str) -> Option<String> {
+        // Collect files and folders of current path, the index keeps them sorted
+        let mut entries: Vec<(&str, Option<&PageFile>)> = self.files.children(path)?.collect();
+
         // Add parent folder if we are not already in the root
         if path != "/" {
-            entries.insert(
-                String::from("../"),
-                ListEntry {
-                    size: None,
-                    hash: None,
-                },
-            );
+            entries.insert(0, ("../", None));
         }
 
-        // Sort all links alphabetically
-        let mut links: Vec<&String> = entries.keys().collect();
-        links.sort();
-
         // Generate HTML
+        let title = escape_html(path);
         let mut html = format!(
             r#"
             <!DOCTYPE html>
             <html lang="en">
             <head>
                 <meta charset="utf-8">
-                <title>Contents of {path}</title>
+                <title>Contents of {title}</title>
                 <link rel="icon" href="data:image/png;base64,iVBORw0KGgo=">
                 <style>
                     body {{ font-family: monospace; }}
@@ -196,7 +609,7 @@ impl Page {
                 </style>
             </head>
             <body>
-                <h1>Contents of {path}</h1>
+                <h1>Contents of {title}</h1>
                 <table>
                     <tr>
                         <th>&nbsp;</th>
@@ -206,29 +619,25 @@ impl Page {
                     </tr>
         "#
         );
-        for link in links {
+        for (link, file) in entries {
             let symbol = if link.ends_with("/") {
                 "&#128193;"
             } else {
                 "&#128196;"
             };
-            let Some(details) = entries.get(link) else {
-                continue;
-            };
-            let size = details
-                .size
-                .map(|s| s.to_string())
+            let size = file
+                .map(|f| f.blob.size().to_string())
                 .unwrap_or(String::from("&nbsp;"));
-            let hash = details
-                .hash
-                .as_deref()
-                .map(|s| s.to_string())
+            let hash = file
+                .map(|f| f.hash.clone())
                 .unwrap_or(String::from("&nbsp;"));
+            let href = escape_html(&encode_path(&format!("{path}{link}")));
+            let link = escape_html(link);
             html.push_str(&format!(
                 r#"
                 <tr>
                     <td>{symbol}</td>
-                    <td><a href="{path}{link}">{link}</a></td>
+                    <td><a href="{href}">{link}</a></td>
                     <td>{size}</td>
                     <td>{hash}</td>
                 </tr>
@@ -240,15 +649,71 @@ impl Page {
     }
 }
 
-pub async fn update_page(page_lock: &RwLock<Page>, temp_folder: &Path) -> Result<()> {
-    let page = page_lock.read().await;
-    let repo = page.repo.clone();
-    let last_hash = page.last_hash.clone();
-    let reference = page.git_ref.clone();
-    let max_bytes = page.max_bytes;
-    let subfolder = page.subfolder.clone();
-    let prefix = page.prefix.clone();
-    drop(page);
+/// Shared resources needed for page updates
+pub struct UpdateContext {
+    pub temp_folder: PathBuf,
+    pub cache: Option<Arc<DiskCache>>,
+    pub blobs: Arc<BlobStore>,
+}
+
+impl UpdateContext {
+    pub fn from_config(config: &Configuration) -> Result<Self> {
+        let cache = config
+            .cache_folder
+            .as_deref()
+            .map(DiskCache::new)
+            .transpose()
+            .context("Failed to set up disk cache")?
+            .map(Arc::new);
+        let blobs = BlobStore::new(
+            config.max_memory_bytes,
+            &config.storage_folder,
+            config.storage_cache_bytes,
+        );
+        Ok(Self {
+            temp_folder: config.temp_folder.clone(),
+            cache,
+            blobs: Arc::new(blobs),
+        })
+    }
+
+    /// Where new file contents are kept for pages with the specified storage
+    pub fn blob_target(&self, storage: PageStorage) -> BlobTarget {
+        match storage {
+            PageStorage::Memory => BlobTarget::Memory,
+            PageStorage::Disk => BlobTarget::Disk(self.blobs.clone()),
+        }
+    }
+}
+
+/// Canonical clean URL for a path without .html extension or trailing slash
+fn clean_url(base: &str, prefix: &str, mode: CleanUrls) -> String {
+    if format!("{base}/") == prefix || mode == CleanUrls::WithSlash {
+        format!("{base}/")
+    } else {
+        base.to_string()
+    }
+}
+
+/// Files of a page from a specific commit
+pub struct PageCheckout {
+    pub commit: String,
+    pub files: Vec<PageFile>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Checks out the files of a page from its git repo using a temporary bare clone
+pub async fn checkout_page(
+    config: &PageConfig,
+    temp_folder: &Path,
+    target: BlobTarget,
+) -> Result<PageCheckout> {
+    let repo = config.repo.clone();
+    let reference = config.git_ref.clone();
+    let subfolder = config.subfolder.clone();
+    let depth = config.git_depth;
+    let limits = config.file_limits();
+    let prefix = &config.prefix;
 
     // Prepare folder path to be used for git bare clone
     let stripped_prefix = prefix
@@ -259,53 +724,171 @@ pub async fn update_page(page_lock: &RwLock<Page>, temp_folder: &Path) -> Result
     } else {
         stripped_prefix
     };
-    let temp_folder = temp_folder.to_path_buf().join(folder);
+    let temp_folder = temp_folder.join(folder);
 
-    let handle =
-        spawn_blocking(move || get_git_files(&repo, reference.as_deref(), &temp_folder, max_bytes));
+    // Only files from the subfolder are returned, with reduced paths
+    let handle = spawn_blocking(move || {
+        get_git_files(
+            &repo,
+            reference.as_deref(),
+            &temp_folder,
+            depth,
+            subfolder.as_deref(),
+            &limits,
+            &target,
+        )
+    });
     let result = handle
         .await
         .context("Failed to join blocking update task")?;
     let checkout = result.context("Failed to get git files")?;
 
+    let mut new_files = Vec::new();
+    for file in checkout.files {
+        new_files.push(PageFile {
+            path: format!("{}{}", prefix, file.path),
+            media_type: media_type_from_path(&file.path).to_string(),
+            hash: file.hash,
+            blob: file.blob,
+            modified: Some(file.modified),
+        });
+    }
+
+    if new_files.is_empty() {
+        bail!("No files found")
+    }
+    Ok(PageCheckout {
+        commit: checkout.hash,
+        files: new_files,
+        skipped: checkout.skipped,
+    })
+}
+
+pub async fn update_page(page_lock: &RwLock<Page>, context: &UpdateContext) -> Result<()> {
+    let page = page_lock.read().await;
+    let config = page.config.clone();
+    let last_hash = page.last_hash.clone();
+    drop(page);
+
+    let target = context.blob_target(config.storage);
+    let checkout = checkout_page(&config, &context.temp_folder, target).await?;
     if let Some(hash) = last_hash
-        && checkout.hash == hash
+        && checkout.commit == hash
     {
         // Early out, git ref has not changed!
         return Ok(());
     }
+    for file in &checkout.skipped {
+        warn!(
+            page = %config.prefix,
+            commit = %checkout.commit,
+            path = %file.path,
+            reason = %file.reason,
+            "Skipped file over limit"
+        );
+    }
 
-    let mut new_files = Vec::new();
-    for file in checkout.files {
-        if let Some(folder) = &subfolder {
-            // Filter out only files from subfolder with reduced paths
-            if let Some(path) = file.path.strip_prefix(folder) {
-                new_files.push(PageFile {
-                    path: format!("{}{}", prefix, path),
-                    media_type: media_type_from_path(path).to_string(),
-                    hash: file.hash,
-                    data: file.data,
-                });
+    // Share identical files with other pages and check the global memory limit
+    let new_files = {
+        let page = page_lock.read().await;
+        let replaced = page.files.iter();
+        context
+            .blobs
+            .intern(checkout.files, replaced, config.storage)?
+    };
+
+    // Persist the new files before publishing them
+    let new_files = if let Some(cache) = context.cache.clone() {
+        let commit = checkout.commit.clone();
+        let handle = spawn_blocking(move || {
+            if let Err(err) = cache.store(&config, &commit, &new_files) {
+                warn!(
+                    page = %config.prefix,
+                    commit = %commit,
+                    error = format!("{err:#}"),
+                    "Failed to store page in disk cache"
+                );
             }
+            new_files
+        });
+        handle.await.context("Failed to join blocking cache task")?
+    } else {
+        new_files
+    };
+
+    let mut page = page_lock.write().await;
+    page.files = PageFiles::new(new_files);
+    page.last_hash = Some(checkout.commit);
+    drop(page);
+
+    // Free the blobs that were only used by the replaced files
+    let blobs = context.blobs.clone();
+    spawn_blocking(move || blobs.collect_garbage())
+        .await
+        .context("Failed to join blocking garbage collection task")
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "on" | "enabled" => Ok(true),
+        "false" | "off" | "disabled" => Ok(false),
+        _ => bail!("Invalid value '{value}', expected true/false, on/off or enabled/disabled"),
+    }
+}
+
+/// Finds the numbers of all pages configured with numbered arguments or environment variables
+/// like `--page3-git-repo` or `PAGE3_GIT_REPO`.
+/// Unknown page arguments are rejected, unknown page environment variables only cause
+/// a warning since the environment can contain unrelated variables with the same prefix.
+pub fn page_numbers() -> Result<BTreeSet<usize>> {
+    let mut numbers = BTreeSet::new();
+    for arg in std::env::args().skip(1) {
+        if !is_page_arg(&arg) {
+            continue;
+        }
+        let rest = arg.trim().trim_start_matches("--page");
+        let (number, rest) = split_number(rest);
+        let Some((key, _)) = rest.strip_prefix("-").and_then(|r| r.split_once("=")) else {
+            bail!("Invalid page argument {arg}, expected --page[N]-<setting>=<value>");
+        };
+        let key = key.to_uppercase().replace("-", "_");
+        ensure!(
+            PAGE_KEYS.contains(&key.as_str()),
+            "Unknown page argument {arg}"
+        );
+        numbers.extend(number);
+    }
+    for (name, _) in std::env::vars() {
+        let Some(rest) = name.strip_prefix("PAGE") else {
+            continue;
+        };
+        let (number, rest) = split_number(rest);
+        let Some(key) = rest.strip_prefix("_") else {
+            continue;
+        };
+        if PAGE_KEYS.contains(&key) {
+            numbers.extend(number);
         } else {
-            // All files are added unfiltered
-            new_files.push(PageFile {
-                path: format!("{}{}", prefix, file.path),
-                media_type: media_type_from_path(&file.path).to_string(),
-                hash: file.hash,
-                data: file.data,
-            });
+            warn!("Ignoring unknown page environment variable {name}");
         }
     }
+    Ok(numbers)
+}
 
-    if new_files.is_empty() {
-        bail!("No files found")
-    }
+/// Page arguments look like `--page-<setting>` or `--page<N>-<setting>`.
+/// Other arguments starting with `--page` like `--page=<name>` belong to commands.
+pub fn is_page_arg(arg: &str) -> bool {
+    arg.trim()
+        .strip_prefix("--page")
+        .is_some_and(|rest| split_number(rest).1.starts_with('-'))
+}
 
-    let mut page = page_lock.write().await;
-    page.files = new_files;
-    page.last_hash = Some(checkout.hash);
-    Ok(())
+/// Splits off the leading page number, if there is one
+fn split_number(value: &str) -> (Option<usize>, &str) {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    (value[..end].parse().ok(), &value[end..])
 }
 
 fn get_cli_arg(name: String) -> Option<String> {
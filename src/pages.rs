@@ -1,133 +1,358 @@
-use crate::page::{Page, update_page};
-use anyhow::{Result, bail, ensure};
-use std::path::Path;
-use tokio::sync::RwLock;
-use tokio::time::Instant;
+use crate::cache::DiskCache;
+use crate::files::PageFiles;
+use crate::page::{
+    CleanUrls, Page, PageConfig, PageSettings, PageStorage, UpdateContext, page_numbers,
+};
+use anyhow::{Context, Result, bail, ensure};
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock, watch};
+use tokio::task::spawn_blocking;
 use tracing::{info, warn};
 
+/// All pages of the application.
+/// The list of pages is replaced as a whole when pages are added, removed or reconfigured,
+/// so that requests and background updates always see a consistent set of pages.
+/// The configuration of a page never changes, reconfigured pages are replaced by a new page
+/// that shares the update jobs of the old page.
 pub struct Pages {
-    pages: Vec<RwLock<Page>>,
+    pages: watch::Sender<Arc<Vec<Arc<RwLock<Page>>>>>,
+    // Serializes all changes to avoid losing concurrent changes
+    changes: Mutex<()>,
+}
+
+/// Names of the pages affected by a configuration change.
+/// Changed pages include the names of the changed settings.
+#[derive(Default)]
+pub struct PageChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, Vec<&'static str>)>,
+}
+
+impl PageChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
 }
 
 impl Pages {
-    pub async fn from_cli_and_env() -> Result<Self> {
-        // Check for single page without numbers
-        let mut pages = Self { pages: Vec::new() };
-        if let Some(page) = Page::from_cli(None) {
-            pages.add_page(page).await?;
-        } else if let Some(page) = Page::from_env(None) {
-            pages.add_page(page).await?;
+    /// Sets up the pages from the config file, command line arguments and environment variables
+    pub async fn from_config(file_pages: Vec<PageSettings>) -> Result<Self> {
+        let configs = page_configs(file_pages)?;
+        let pages = Self {
+            pages: watch::Sender::new(Arc::new(Vec::new())),
+            changes: Mutex::new(()),
+        };
+        pages
+            .change(|current| {
+                *current = configs;
+                Ok(())
+            })
+            .await?;
+        Ok(pages)
+    }
+
+    /// Applies a change to the list of page configurations.
+    /// The new configurations are validated and the list of pages is only replaced if valid.
+    /// Unchanged pages are kept as they are. Changed pages are replaced and keep their jobs.
+    /// They also keep their files until the next update if their prefix is unchanged.
+    /// Without the same content the last commit is cleared, so that the next update
+    /// replaces the files even if the commit did not change.
+    pub async fn change(
+        &self,
+        change: impl FnOnce(&mut Vec<PageConfig>) -> Result<()>,
+    ) -> Result<PageChanges> {
+        let _guard = self.changes.lock().await;
+
+        let current = self.snapshot();
+        let mut current_configs = Vec::new();
+        for page_lock in current.iter() {
+            current_configs.push(page_lock.read().await.config.clone());
         }
-        if !pages.pages.is_empty() {
-            return Ok(pages);
+        let mut configs = current_configs.clone();
+        change(&mut configs)?;
+        for (i, config) in configs.iter().enumerate() {
+            validate(config, &configs[..i])?;
         }
 
-        // Number pages from 0..n
-        let mut n = 0;
-        loop {
-            if let Some(page) = Page::from_cli(Some(n)) {
-                pages.add_page(page).await?;
-            } else if let Some(page) = Page::from_env(Some(n)) {
-                pages.add_page(page).await?;
-            } else {
-                break;
+        let mut changes = PageChanges::default();
+        for config in &current_configs {
+            if !configs.iter().any(|c| c.name == config.name) {
+                changes.removed.push(config.name.clone());
             }
-            n += 1;
+        }
+        let mut pages = Vec::new();
+        for config in configs {
+            let existing = current_configs.iter().position(|c| c.name == config.name);
+            let Some(index) = existing else {
+                changes.added.push(config.name.clone());
+                pages.push(Arc::new(RwLock::new(Page::new(config))));
+                continue;
+            };
+            if current_configs[index] == config {
+                pages.push(current[index].clone());
+                continue;
+            }
+            let settings = config.differences(&current_configs[index]);
+            changes.changed.push((config.name.clone(), settings));
+            let old_page = current[index].read().await;
+            let mut page = Page::new(config);
+            page.jobs = old_page.jobs.clone();
+            // Old files are served until the next update replaces them
+            if page.config.prefix == old_page.config.prefix {
+                page.files = old_page.files.clone();
+            }
+            if page.config.same_content(&old_page.config) {
+                page.last_hash = old_page.last_hash.clone();
+            }
+            pages.push(Arc::new(RwLock::new(page)));
         }
 
-        Ok(pages)
+        // Replaced pages are freed as soon as all running requests and updates are finished
+        self.pages.send_replace(Arc::new(pages));
+        Ok(changes)
     }
 
-    async fn add_page(&mut self, new_page: Page) -> Result<()> {
-        ensure!(
-            new_page.prefix.starts_with("/"),
-            "Prefix must start with slash"
-        );
-        ensure!(new_page.prefix.ends_with("/"), "Prefix must end with slash");
-        for page_lock in &self.pages {
-            let page = page_lock.read().await;
-            if page.prefix == new_page.prefix {
-                bail!("Prefix {} is already used by another page", page.prefix);
+    /// Loads the last successful snapshot of all pages from the disk cache
+    pub async fn load_cache(&self, cache: &Arc<DiskCache>, context: &UpdateContext) {
+        for page_lock in self.snapshot().iter() {
+            let config = page_lock.read().await.config.clone();
+            let prefix = config.prefix.clone();
+            let cache = cache.clone();
+            let storage = config.storage;
+            let target = context.blob_target(storage);
+            let handle = spawn_blocking(move || cache.load(&config, &target));
+            match handle.await {
+                Ok(Ok(Some(cached))) => {
+                    let files =
+                        match context
+                            .blobs
+                            .intern(cached.files, std::iter::empty(), storage)
+                        {
+                            Ok(files) => files,
+                            Err(err) => {
+                                warn!(
+                                    page = %prefix,
+                                    error = format!("{err:#}"),
+                                    "Failed to load page from disk cache"
+                                );
+                                continue;
+                            }
+                        };
+                    info!(
+                        page = %prefix,
+                        commit = %cached.commit,
+                        files = files.len(),
+                        "Loaded page from disk cache"
+                    );
+                    let mut page = page_lock.write().await;
+                    page.files = PageFiles::new(files);
+                    page.last_hash = Some(cached.commit);
+                }
+                Ok(Ok(None)) => info!(page = %prefix, "Found no disk cache entry for page"),
+                Ok(Err(err)) => warn!(
+                    page = %prefix,
+                    error = format!("{err:#}"),
+                    "Failed to load page from disk cache"
+                ),
+                Err(err) => warn!("Failed to join blocking cache task: {err}"),
             }
-            if page.prefix.starts_with(&new_page.prefix)
-                || new_page.prefix.starts_with(&page.prefix)
-            {
-                bail!(
-                    "Existing prefix {} conflicts with new prefix {}",
-                    page.prefix,
-                    new_page.prefix
-                );
+        }
+    }
+
+    /// True if any page keeps its files in the storage folder
+    pub async fn use_disk_storage(&self) -> bool {
+        for page_lock in self.snapshot().iter() {
+            if page_lock.read().await.config.storage == PageStorage::Disk {
+                return true;
             }
         }
-        self.pages.push(RwLock::new(new_page));
-        Ok(())
+        false
     }
 
-    pub async fn update(&self, temp_folder: &Path) {
-        for page_lock in &self.pages {
-            let start = Instant::now();
-            let result = update_page(page_lock, temp_folder).await;
-            let duration = start.elapsed();
+    pub async fn find_page(&self, path: &str) -> Option<Arc<RwLock<Page>>> {
+        for page_lock in self.snapshot().iter() {
             let page = page_lock.read().await;
-            if let Err(error) = result {
-                warn!(
-                    "Failed to update page {} from repo {} for ref {:?} after {:?}: {:#}",
-                    page.prefix, page.repo, page.git_ref, duration, error
-                );
-            } else {
-                info!(
-                    "Updated page {} from repo {} for ref {:?} after {:?}",
-                    page.prefix, page.repo, page.git_ref, duration
-                );
+            if path.starts_with(&page.config.prefix) {
+                return Some(page_lock.clone());
             }
         }
+        None
     }
 
-    pub async fn find_page(&self, path: &str) -> Option<&RwLock<Page>> {
-        for page_lock in &self.pages {
-            let page = page_lock.read().await;
-            if path.starts_with(&page.prefix) {
-                return Some(page_lock);
+    pub async fn find_page_by_name(&self, name: &str) -> Option<Arc<RwLock<Page>>> {
+        for page_lock in self.snapshot().iter() {
+            if page_lock.read().await.config.name == name {
+                return Some(page_lock.clone());
             }
         }
         None
     }
 
+    /// Returns the current list of pages
+    pub fn snapshot(&self) -> Arc<Vec<Arc<RwLock<Page>>>> {
+        self.pages.borrow().clone()
+    }
+
+    /// Notifies about every change of the list of pages
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Vec<Arc<RwLock<Page>>>>> {
+        self.pages.subscribe()
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.pages.is_empty()
+        self.pages.borrow().is_empty()
     }
 
     pub async fn log(&self) {
-        let b2o = |value: bool| -> &'static str {
-            match value {
-                true => "on",
-                false => "off",
-            }
+        let pages = self.snapshot();
+        info!("Found {} configured pages", pages.len());
+        for page_lock in pages.iter() {
+            log_page(&page_lock.read().await.config);
+        }
+    }
+}
+
+/// Combines the pages from the config file with the pages from command line arguments
+/// and environment variables. Their settings override single settings of the file page
+/// with the same name, other pages are added and need a git repo.
+pub fn page_configs(file_pages: Vec<PageSettings>) -> Result<Vec<PageConfig>> {
+    let mut pages = file_pages;
+
+    // Single page without number and numbered pages
+    let mut numbers: Vec<_> = page_numbers()?.into_iter().map(Some).collect();
+    numbers.insert(0, None);
+    for number in numbers {
+        let mut settings = PageConfig::env_settings(number);
+        if settings.is_empty() {
+            continue;
+        }
+        let page = match number {
+            Some(number) => format!("page{number}"),
+            None => String::from("page"),
         };
-        info!("Found {} configured pages", self.pages.len());
-        for (i, page_lock) in self.pages.iter().enumerate() {
-            let page = page_lock.read().await;
-            info!(
-                "Page {i} running on path {} for repo {} and ref {:?}",
-                page.prefix, page.repo, page.git_ref
-            );
-            info!(
-                "Page {i} has auto index {} and auto list {}",
-                b2o(page.auto_index),
-                b2o(page.auto_list)
+        let name = settings.get("NAME").cloned().unwrap_or(page.clone());
+        if let Some(existing) = pages.iter_mut().find(|p| p.get("NAME") == Some(&name)) {
+            existing.extend(settings);
+            continue;
+        }
+        ensure!(
+            settings.contains_key("GIT_REPO"),
+            "Page settings {}_* or --{page}-* are not used by any page, \
+            they need a git repo or the name of a page from the config file",
+            page.to_uppercase()
+        );
+        settings.insert(String::from("NAME"), name);
+        pages.push(settings);
+    }
+
+    pages
+        .iter()
+        .map(|settings| {
+            let name = settings.get("NAME").map(|n| n.as_str()).unwrap_or_default();
+            PageConfig::from_settings(settings).context(format!("Invalid page {name}"))
+        })
+        .collect()
+}
+
+/// Checks a new page configuration against the configurations of the other pages
+fn validate(new_page: &PageConfig, others: &[PageConfig]) -> Result<()> {
+    ensure!(
+        new_page.prefix.starts_with("/"),
+        "Prefix must start with slash"
+    );
+    ensure!(new_page.prefix.ends_with("/"), "Prefix must end with slash");
+    for page in others {
+        if page.name == new_page.name {
+            bail!("Name {} is already used by another page", page.name);
+        }
+        if page.prefix == new_page.prefix {
+            bail!("Prefix {} is already used by another page", page.prefix);
+        }
+        if page.prefix.starts_with(&new_page.prefix) || new_page.prefix.starts_with(&page.prefix) {
+            bail!(
+                "Existing prefix {} conflicts with new prefix {}",
+                page.prefix,
+                new_page.prefix
             );
-            if let Some(max) = page.max_bytes {
-                info!("Page {i} has a max limit of {max} bytes configured");
-            }
-            if let Some(folder) = &page.subfolder {
-                info!("Page {i} is limited to subfolder {folder}");
-            }
-            if let Some(secret) = &page.update_secret {
-                info!(
-                    "Page {i} has a GET update hook enabled at {}update/{secret}",
-                    page.prefix
-                );
-            }
         }
     }
+    Ok(())
+}
+
+pub fn log_page(page: &PageConfig) {
+    let b2o = |value: bool| -> &'static str {
+        match value {
+            true => "on",
+            false => "off",
+        }
+    };
+    let name = &page.name;
+    info!(
+        "Page {name} running on path {} for repo {} and ref {:?}",
+        page.prefix,
+        page.redacted_repo(),
+        page.git_ref
+    );
+    info!(
+        "Page {name} has auto index {} and auto list {}",
+        b2o(page.auto_index),
+        b2o(page.auto_list)
+    );
+    if page.clean_urls != CleanUrls::Off {
+        info!("Page {name} has clean URLs {}", page.clean_urls);
+    }
+    if !page.cache_control.is_empty() {
+        info!(
+            "Page {name} has cache control rules '{}'",
+            page.cache_control
+        );
+    }
+    if let Some(cors) = &page.cors {
+        info!("Page {name} allows cross-origin requests from {cors}");
+    }
+    if page.storage != PageStorage::Memory {
+        info!("Page {name} has {} storage", page.storage);
+    }
+    if let Some(max) = page.max_bytes {
+        info!("Page {name} has a max limit of {max} bytes configured");
+    }
+    if let Some(max) = page.max_file_bytes {
+        let policy = page.max_file_bytes_policy;
+        info!("Page {name} has a max limit of {max} bytes per file ({policy})");
+    }
+    if let Some(max) = page.max_files {
+        let policy = page.max_files_policy;
+        info!("Page {name} has a max limit of {max} files ({policy})");
+    }
+    if let Some(max) = page.max_path_depth {
+        let policy = page.max_path_depth_policy;
+        info!("Page {name} has a max path depth of {max} ({policy})");
+    }
+    if let Some(folder) = &page.subfolder {
+        info!("Page {name} is limited to subfolder {folder}");
+    }
+    if page.git_depth.get() > 1 {
+        info!("Page {name} fetches the last {} commits", page.git_depth);
+    }
+    if let Some(interval) = page.interval {
+        info!("Page {name} has an update interval of {interval} sec");
+    }
+    if let Some(cron) = &page.cron {
+        info!("Page {name} has a cron update schedule '{cron}' (UTC)");
+    }
+    if page.webhook_only {
+        info!("Page {name} is only updated via webhook after startup");
+    }
+    if page.update_secret.is_some() {
+        info!(
+            "Page {name} has a GET update hook enabled at {}update/<secret>",
+            page.prefix
+        );
+    }
+    if page.webhook_secret.is_some() {
+        info!(
+            "Page {name} has a signed POST webhook enabled at {}update",
+            page.prefix
+        );
+    }
 }
@@ -0,0 +1,95 @@
+use crate::config::Configuration;
+use crate::config_file::ConfigFile;
+use crate::pages::{Pages, log_page, page_configs};
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::interval;
+use tracing::{info, warn};
+
+/// Reloads the config file on SIGHUP or when the file was modified.
+/// Changed pages are applied without restarting the server.
+/// If the new config file is invalid, the current pages are kept.
+pub async fn reload_config(config: Arc<Configuration>, pages: Arc<Pages>, mut file: ConfigFile) {
+    let Some(path) = config.config_file.clone() else {
+        return;
+    };
+    let mut modified = modified_time(&path);
+    let watch = config.config_watch_interval > 0;
+    let mut check = interval(Duration::from_secs(config.config_watch_interval.max(1)));
+
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("Failed to install signal handler");
+
+    loop {
+        #[cfg(unix)]
+        let hangup_signal = hangup.recv();
+        #[cfg(not(unix))]
+        let hangup_signal = std::future::pending::<Option<()>>();
+
+        let reason = tokio::select! {
+            _ = hangup_signal => "SIGHUP",
+            _ = check.tick(), if watch => {
+                let current = modified_time(&path);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                "file change"
+            },
+        };
+
+        info!("Reloading config file {} after {reason}...", path.display());
+        match reload(&path, &pages, &file).await {
+            Ok(new_file) => file = new_file,
+            Err(err) => warn!("Failed to reload config file, keeping current config: {err:#}"),
+        }
+    }
+}
+
+async fn reload(path: &Path, pages: &Pages, file: &ConfigFile) -> Result<ConfigFile> {
+    let new_file = ConfigFile::load(path)?;
+    let configs = page_configs(new_file.pages.clone())?;
+    let changes = pages
+        .change(|current| {
+            *current = configs.clone();
+            Ok(())
+        })
+        .await?;
+
+    for name in &changes.removed {
+        info!("Page {name} was removed");
+    }
+    for name in &changes.added {
+        info!("Page {name} was added");
+    }
+    for (name, settings) in &changes.changed {
+        info!("Page {name} was changed: {}", settings.join(", "));
+    }
+    for config in &configs {
+        let added = changes.added.contains(&config.name);
+        let changed = changes.changed.iter().any(|(name, _)| *name == config.name);
+        if added || changed {
+            log_page(config);
+        }
+    }
+    if changes.is_empty() {
+        info!("Config file reloaded without page changes");
+    }
+
+    let globals = file.changed_globals(&new_file);
+    if !globals.is_empty() {
+        warn!(
+            "Changed global settings need a restart to be applied: {}",
+            globals.join(", ")
+        );
+    }
+
+    Ok(new_file)
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
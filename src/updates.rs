@@ -1,24 +1,154 @@
 use crate::config::Configuration;
+use crate::metrics::metrics;
+use crate::page::{Page, UpdateContext, UpdateSchedule};
 use crate::pages::Pages;
+use jiff::Timestamp;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::RwLock;
 use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use tokio::task::{JoinSet, spawn_blocking};
 use tokio::time::sleep;
-use tracing::info;
+use tracing::{debug, warn};
 
 pub async fn background_updates(
     pages: Arc<Pages>,
     config: Arc<Configuration>,
+    context: Arc<UpdateContext>,
     mut stopper: Receiver<()>,
 ) {
+    // Every page gets its own update loop with an individual schedule.
+    // When the list of pages changes, the loops of removed or replaced pages are stopped
+    // and new loops are started for added or replaced pages.
+    // The cache entries and metrics of prefixes that are no longer used are removed.
+    let mut receiver = pages.subscribe();
+    let mut running: Vec<(Arc<RwLock<Page>>, String, watch::Sender<bool>)> = Vec::new();
+    let mut tasks = JoinSet::new();
+    // Only the loops started with the application are delayed,
+    // added and changed pages are updated right away.
+    let mut startup = true;
     loop {
-        info!("Starting background updates...");
-        pages.update(&config.temp_folder).await;
-        info!("Finished background updates");
+        let current = receiver.borrow_and_update().clone();
+        let mut prefixes = HashSet::new();
+        for page_lock in current.iter() {
+            prefixes.insert(page_lock.read().await.config.prefix.clone());
+        }
+        let mut removed = HashSet::new();
+        running.retain(|(page_lock, prefix, stop_sender)| {
+            let keep = current.iter().any(|p| Arc::ptr_eq(p, page_lock));
+            if !keep {
+                stop_sender.send_replace(true);
+                if !prefixes.contains(prefix) {
+                    removed.insert(prefix.clone());
+                }
+            }
+            keep
+        });
+        for prefix in removed {
+            remove_page_data(&context, prefix).await;
+        }
+        for page_lock in current.iter() {
+            if running.iter().any(|(p, _, _)| Arc::ptr_eq(p, page_lock)) {
+                continue;
+            }
+            let prefix = page_lock.read().await.config.prefix.clone();
+            let (stop_sender, stop_receiver) = watch::channel(false);
+            let page_lock_clone = page_lock.clone();
+            let config = config.clone();
+            let context = context.clone();
+            tasks.spawn(async move {
+                page_updates(page_lock_clone, config, context, startup, stop_receiver).await
+            });
+            running.push((page_lock.clone(), prefix, stop_sender));
+        }
+        startup = false;
+        while tasks.try_join_next().is_some() {}
 
         tokio::select! {
-            _ = sleep(Duration::from_secs(config.interval)) => {},
-            _ = stopper.recv() => { break; },
+            _ = stopper.recv() => break,
+            result = receiver.changed() => if result.is_err() { break; },
+        }
+    }
+
+    for (_, _, stop_sender) in running {
+        stop_sender.send_replace(true);
+    }
+    tasks.join_all().await;
+}
+
+async fn page_updates(
+    page_lock: Arc<RwLock<Page>>,
+    config: Arc<Configuration>,
+    context: Arc<UpdateContext>,
+    startup: bool,
+    mut stopper: watch::Receiver<bool>,
+) {
+    // Random delay to avoid that many instances hit the git server at once
+    if startup {
+        let max_jitter = Duration::from_secs(config.startup_jitter);
+        let jitter = max_jitter.mul_f64(fastrand::f64());
+        if !wait(jitter, &mut stopper).await {
+            return;
+        }
+    }
+
+    let page = page_lock.read().await;
+    let schedule = page.config.schedule(config.interval);
+    let jobs = page.jobs.clone();
+    let prefix = page.config.prefix.clone();
+    drop(page);
+    loop {
+        if jobs.is_paused() {
+            debug!(page = %prefix, "Skipped background update of paused page");
+        } else {
+            let job = jobs.trigger(page_lock.clone(), context.clone(), "background update");
+            tokio::select! {
+                _ = jobs.wait(job) => {},
+                _ = stopper.wait_for(|stop| *stop) => { return; },
+            }
         }
+
+        let delay = match &schedule {
+            UpdateSchedule::Interval(interval) => *interval,
+            UpdateSchedule::Cron(cron) => {
+                let now = Timestamp::now();
+                let Some(next) = cron.next_after(now) else {
+                    warn!(page = %prefix, %cron, "Cron schedule has no future execution time");
+                    return;
+                };
+                now.duration_until(next).unsigned_abs()
+            }
+            UpdateSchedule::WebhookOnly => return,
+        };
+        if !wait(delay, &mut stopper).await {
+            return;
+        }
+    }
+}
+
+/// Removes the cache entry and the metrics of a page prefix that is no longer used
+async fn remove_page_data(context: &UpdateContext, prefix: String) {
+    metrics().remove_page(&prefix);
+    let blobs = context.blobs.clone();
+    if let Err(err) = spawn_blocking(move || blobs.collect_garbage()).await {
+        warn!("Failed to join blocking garbage collection task: {err}");
+    }
+    let Some(cache) = context.cache.clone() else {
+        return;
+    };
+    match spawn_blocking(move || cache.remove(&prefix)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => warn!("Failed to remove page from disk cache: {err:#}"),
+        Err(err) => warn!("Failed to join blocking cache task: {err}"),
+    }
+}
+
+/// Returns false if the stop signal was received while waiting
+async fn wait(duration: Duration, stopper: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => true,
+        _ = stopper.wait_for(|stop| *stop) => false,
     }
 }
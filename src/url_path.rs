@@ -0,0 +1,121 @@
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+
+/// Characters that need to be encoded in URL paths (slashes are kept as separators)
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'\'')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Decodes the percent-encoded path of a request and normalizes it.
+/// Empty and `.` segments are removed, `..` segments remove the previous segment
+/// but never go above the root. A trailing slash is kept.
+/// Returns nothing for paths with encoded slashes, NUL bytes or invalid UTF-8.
+pub fn normalize_path(raw: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    let mut trailing_slash = false;
+    for segment in raw.split('/') {
+        let decoded = percent_decode_str(segment).decode_utf8().ok()?;
+        if decoded.contains(['/', '\0']) {
+            return None;
+        }
+        trailing_slash = matches!(&*decoded, "" | "." | "..");
+        match &*decoded {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(decoded),
+        }
+    }
+
+    let mut path = String::from("/");
+    path.push_str(&segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// Percent-encodes a decoded path for use in links and redirects
+pub fn encode_path(path: &str) -> String {
+    utf8_percent_encode(path, PATH).to_string()
+}
+
+/// Escapes text for use in HTML content and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_normal_paths() {
+        assert_eq!(normalize_path("/").unwrap(), "/");
+        assert_eq!(normalize_path("/index.html").unwrap(), "/index.html");
+        assert_eq!(normalize_path("/docs/").unwrap(), "/docs/");
+        assert_eq!(normalize_path("/docs/a.txt").unwrap(), "/docs/a.txt");
+    }
+
+    #[test]
+    fn decode_segments() {
+        assert_eq!(normalize_path("/My%20File.pdf").unwrap(), "/My File.pdf");
+        assert_eq!(normalize_path("/%C3%A4.txt").unwrap(), "/ä.txt");
+        assert_eq!(normalize_path("/a%3Fb").unwrap(), "/a?b");
+    }
+
+    #[test]
+    fn remove_empty_and_dot_segments() {
+        assert_eq!(normalize_path("").unwrap(), "/");
+        assert_eq!(normalize_path("//a//b").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a/./b").unwrap(), "/a/b");
+        assert_eq!(normalize_path("/a/b/.").unwrap(), "/a/b/");
+        assert_eq!(normalize_path("/a/%2e/b").unwrap(), "/a/b");
+    }
+
+    #[test]
+    fn resolve_parent_segments() {
+        assert_eq!(normalize_path("/a/./b/../c").unwrap(), "/a/c");
+        assert_eq!(normalize_path("/a/b/..").unwrap(), "/a/");
+        assert_eq!(normalize_path("/a/%2E%2E/b").unwrap(), "/b");
+        assert_eq!(normalize_path("/..").unwrap(), "/");
+        assert_eq!(normalize_path("/../../etc/passwd").unwrap(), "/etc/passwd");
+        assert_eq!(normalize_path("/a/../..").unwrap(), "/");
+    }
+
+    #[test]
+    fn reject_invalid_paths() {
+        assert_eq!(normalize_path("/a%2Fb"), None);
+        assert_eq!(normalize_path("/a%2fb"), None);
+        assert_eq!(normalize_path("/a%00b"), None);
+        assert_eq!(normalize_path("/%FF"), None);
+        assert_eq!(normalize_path("/%C3"), None);
+    }
+
+    #[test]
+    fn encode_paths() {
+        assert_eq!(encode_path("/My File.pdf"), "/My%20File.pdf");
+        assert_eq!(encode_path("/a?b#c"), "/a%3Fb%23c");
+        assert_eq!(encode_path("/ä/"), "/%C3%A4/");
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{Context, Result, bail, ensure};
+use hmac::{Hmac, Mac};
+use hyper::header::HeaderMap;
+use serde::Deserialize;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+/// Git forges with supported webhook authentication
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+#[derive(Deserialize)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    // Used by GitHub and Gitea/Forgejo
+    repository: Option<RepositoryInfo>,
+    // Used by GitLab
+    project: Option<RepositoryInfo>,
+}
+
+#[derive(Deserialize)]
+struct RepositoryInfo {
+    default_branch: Option<String>,
+}
+
+pub struct PushEvent {
+    pub git_ref: String,
+    pub default_branch: Option<String>,
+}
+
+/// Checks the forge specific signature or token headers of a webhook request.
+/// All comparisons of secrets and signatures are done in constant time.
+pub fn verify_request(headers: &HeaderMap, body: &[u8], secret: &str) -> Result<Forge> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    if let Some(signature) = header("X-Hub-Signature-256") {
+        // GitHub (also sent by Gitea and Forgejo)
+        let hex = signature
+            .strip_prefix("sha256=")
+            .context("Signature is missing the sha256= prefix")?;
+        verify_hmac(body, secret, hex)?;
+        let gitea = header("X-Gitea-Event").is_some() || header("X-Forgejo-Event").is_some();
+        let forge = if gitea { Forge::Gitea } else { Forge::GitHub };
+        Ok(forge)
+    } else if let Some(signature) = header("X-Gitea-Signature") {
+        // Gitea without the GitHub compatible header
+        verify_hmac(body, secret, signature)?;
+        Ok(Forge::Gitea)
+    } else if let Some(signature) = header("X-Forgejo-Signature") {
+        // Forgejo without the GitHub compatible header
+        verify_hmac(body, secret, signature)?;
+        Ok(Forge::Gitea)
+    } else if let Some(token) = header("X-Gitlab-Token") {
+        // GitLab only sends the plain secret token
+        ensure!(
+            bool::from(token.as_bytes().ct_eq(secret.as_bytes())),
+            "Invalid GitLab token"
+        );
+        Ok(Forge::GitLab)
+    } else {
+        bail!("Found no supported webhook signature or token header");
+    }
+}
+
+/// Returns true for push events and false for any other events like pings
+pub fn is_push_event(headers: &HeaderMap, forge: Forge) -> bool {
+    let name = match forge {
+        Forge::GitHub => "X-GitHub-Event",
+        Forge::GitLab => "X-Gitlab-Event",
+        Forge::Gitea if headers.contains_key("X-Forgejo-Event") => "X-Forgejo-Event",
+        Forge::Gitea => "X-Gitea-Event",
+    };
+    match headers.get(name).and_then(|v| v.to_str().ok()) {
+        Some(event) => event == "push" || event == "Push Hook",
+        // Assume push events if the forge does not tell us the event type
+        None => true,
+    }
+}
+
+pub fn parse_push_event(body: &[u8]) -> Result<PushEvent> {
+    let payload: PushPayload =
+        serde_json::from_slice(body).context("Failed to parse JSON push event payload")?;
+    let default_branch = payload
+        .repository
+        .or(payload.project)
+        .and_then(|r| r.default_branch);
+    Ok(PushEvent {
+        git_ref: payload.git_ref,
+        default_branch,
+    })
+}
+
+impl PushEvent {
+    /// Checks if the pushed ref is the one served by a page.
+    /// Short page refs match pushed branches and tags with the same name.
+    /// Pages without a ref follow the default branch of the repository.
+    pub fn matches(&self, page_ref: Option<&str>) -> bool {
+        let Some(name) = page_ref.or(self.default_branch.as_deref()) else {
+            // Without any info about the default branch we need to update to be sure
+            return true;
+        };
+        self.git_ref == name
+            || self.git_ref == format!("refs/heads/{name}")
+            || self.git_ref == format!("refs/tags/{name}")
+    }
+}
+
+fn verify_hmac(body: &[u8], secret: &str, hex_signature: &str) -> Result<()> {
+    let signature = decode_hex(hex_signature).context("Invalid hex encoding of signature")?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .ok()
+        .context("Failed to create HMAC from secret")?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .ok()
+        .context("Invalid webhook signature")
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example from the GitHub documentation about validating webhook deliveries
+    const SECRET: &str = "It's a Secret to Everybody";
+    const BODY: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str = "757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn event(git_ref: &str, default_branch: Option<&str>) -> PushEvent {
+        PushEvent {
+            git_ref: git_ref.to_string(),
+            default_branch: default_branch.map(|b| b.to_string()),
+        }
+    }
+
+    #[test]
+    fn github_signature() {
+        let signature = format!("sha256={SIGNATURE}");
+        let request = headers(&[("X-Hub-Signature-256", &signature)]);
+        assert_eq!(
+            verify_request(&request, BODY, SECRET).unwrap(),
+            Forge::GitHub
+        );
+        let request = headers(&[
+            ("X-Hub-Signature-256", &signature),
+            ("X-Gitea-Event", "push"),
+        ]);
+        assert_eq!(
+            verify_request(&request, BODY, SECRET).unwrap(),
+            Forge::Gitea
+        );
+    }
+
+    #[test]
+    fn gitea_signature() {
+        let request = headers(&[("X-Gitea-Signature", SIGNATURE)]);
+        assert_eq!(
+            verify_request(&request, BODY, SECRET).unwrap(),
+            Forge::Gitea
+        );
+        let request = headers(&[("X-Forgejo-Signature", SIGNATURE)]);
+        assert_eq!(
+            verify_request(&request, BODY, SECRET).unwrap(),
+            Forge::Gitea
+        );
+    }
+
+    #[test]
+    fn bad_signatures() {
+        let signature = format!("sha256={SIGNATURE}");
+        let request = headers(&[("X-Hub-Signature-256", &signature)]);
+        assert!(verify_request(&request, b"Hello, World?", SECRET).is_err());
+        assert!(verify_request(&request, BODY, "Another secret").is_err());
+
+        let request = headers(&[("X-Hub-Signature-256", SIGNATURE)]);
+        assert!(verify_request(&request, BODY, SECRET).is_err());
+        let truncated = &SIGNATURE[..62];
+        let request = headers(&[("X-Gitea-Signature", truncated)]);
+        assert!(verify_request(&request, BODY, SECRET).is_err());
+        let request = headers(&[("X-Gitea-Signature", "not hex")]);
+        assert!(verify_request(&request, BODY, SECRET).is_err());
+        assert!(verify_request(&HeaderMap::new(), BODY, SECRET).is_err());
+    }
+
+    #[test]
+    fn gitlab_token() {
+        let request = headers(&[("X-Gitlab-Token", SECRET)]);
+        assert_eq!(
+            verify_request(&request, BODY, SECRET).unwrap(),
+            Forge::GitLab
+        );
+        let request = headers(&[("X-Gitlab-Token", "It's a Secret")]);
+        assert!(verify_request(&request, BODY, SECRET).is_err());
+        let request = headers(&[("X-Gitlab-Token", "")]);
+        assert!(verify_request(&request, BODY, SECRET).is_err());
+    }
+
+    #[test]
+    fn push_event_refs() {
+        assert!(event("refs/heads/main", None).matches(Some("main")));
+        assert!(event("refs/heads/main", None).matches(Some("refs/heads/main")));
+        assert!(!event("refs/heads/dev", None).matches(Some("main")));
+        assert!(!event("refs/heads/main-old", None).matches(Some("main")));
+        assert!(event("refs/tags/v1.0", None).matches(Some("v1.0")));
+        assert!(event("refs/tags/v1.0", None).matches(Some("refs/tags/v1.0")));
+        assert!(!event("refs/tags/v1.1", None).matches(Some("v1.0")));
+        assert!(!event("refs/tags/v1.0", None).matches(Some("refs/heads/v1.0")));
+    }
+
+    #[test]
+    fn push_event_default_branch() {
+        assert!(event("refs/heads/main", Some("main")).matches(None));
+        assert!(!event("refs/heads/dev", Some("main")).matches(None));
+        assert!(event("refs/heads/dev", None).matches(None));
+    }
+
+    #[test]
+    fn parse_payloads() {
+        let github = br#"{"ref": "refs/heads/main", "repository": {"default_branch": "main"}}"#;
+        let event = parse_push_event(github).unwrap();
+        assert_eq!(event.git_ref, "refs/heads/main");
+        assert_eq!(event.default_branch.as_deref(), Some("main"));
+
+        let gitlab = br#"{"ref": "refs/heads/dev", "project": {"default_branch": "trunk"}}"#;
+        let event = parse_push_event(gitlab).unwrap();
+        assert_eq!(event.default_branch.as_deref(), Some("trunk"));
+
+        assert!(parse_push_event(br#"{"zen": "ping"}"#).is_err());
+    }
+}